@@ -0,0 +1,34 @@
+//! Time inserts, deletes and lookups in a `Line` of 10k graphemes, which
+//! should take about as long as in a short `Line`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use repl_block::prelude::*;
+
+const LEN: usize = 10_000;
+
+fn long_line() -> Line {
+    let text: String = "αβγ de\u{301}f ".repeat(LEN / 8);
+    Line::from(text.as_str())
+}
+
+fn bench_edits(c: &mut Criterion) {
+    let mut group = c.benchmark_group("10k grapheme line");
+    group.bench_function("insert", |b| {
+        b.iter_batched_ref(long_line, |line| line.insert_char((LEN / 2) as u16, 'x'), BatchSize::SmallInput)
+    });
+    group.bench_function("delete", |b| {
+        b.iter_batched_ref(long_line, |line| line.rm_grapheme_at((LEN / 2) as u16), BatchSize::SmallInput)
+    });
+    let line = long_line();
+    group.bench_function("byte offset lookup", |b| {
+        b.iter(|| line.byte_offset_of(std::hint::black_box((LEN * 3 / 4) as u16)))
+    });
+    let offset = line.byte_offset_of((LEN * 3 / 4) as u16);
+    group.bench_function("grapheme index lookup", |b| {
+        b.iter(|| line.grapheme_idx_of(std::hint::black_box(offset)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_edits);
+criterion_main!(benches);
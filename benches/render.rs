@@ -0,0 +1,74 @@
+//! Compare redrawing the input area in full to redrawing only what changed,
+//! when typing at the end of a line in the middle of a long multi-line cmd.
+//! The throughput is the number of bytes written per keystroke.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use repl_block::bench::{Frame, Screen};
+use repl_block::prelude::*;
+
+const WIDTH: u16 = 80;
+const TERM_HEIGHT: u16 = 50;
+const NUM_LINES: usize = 40;
+
+/// Draw a `Frame` for a cmd of `lines`, as the `Repl` would.
+fn frame_of(lines: &[String], cursor: Coords) -> Frame {
+    let mut frame = Frame::new(WIDTH, lines.len() as u16 + 1);
+    for (idx, line) in lines.iter().enumerate() {
+        frame.print_styled(&if idx == 0 { "> ".green() } else { ". ".dark_grey() });
+        frame.print(line);
+        frame.next_line();
+    }
+    frame.print_styled(&"[status]".reverse());
+    frame.set_cursor(cursor.x, cursor.y);
+    frame
+}
+
+/// Return the frames before and after typing a single key.
+fn keystroke() -> (Frame, Frame) {
+    let mut lines: Vec<String> = (0..NUM_LINES)
+        .map(|idx| format!("let value_{idx} = compute({idx}, \"some argument\");"))
+        .collect();
+    let y = NUM_LINES / 2;
+    let cursor_at = |line: &String| Coords { x: 2 + line.len() as u16, y: y as u16 };
+    let before = frame_of(&lines, cursor_at(&lines[y]));
+    lines[y].push('x');
+    let after = frame_of(&lines, cursor_at(&lines[y]));
+    (before, after)
+}
+
+fn bytes_written(screen: &mut Screen, frame: Frame) -> Vec<u8> {
+    let mut sink = vec![];
+    screen.draw(frame, TERM_HEIGHT, &mut sink).unwrap();
+    sink
+}
+
+fn bench_keystroke(c: &mut Criterion) {
+    let (before, after) = keystroke();
+    let mut group = c.benchmark_group("keystroke");
+
+    let full = bytes_written(&mut Screen::default(), after.clone()).len();
+    group.throughput(Throughput::Bytes(full as u64));
+    group.bench_function("full redraw", |b| {
+        b.iter(|| bytes_written(&mut Screen::default(), after.clone()))
+    });
+
+    let mut screen = Screen::default();
+    bytes_written(&mut screen, before.clone());
+    let diffed = bytes_written(&mut screen, after.clone()).len();
+    group.throughput(Throughput::Bytes(diffed as u64));
+    group.bench_function("diffed redraw", |b| {
+        b.iter_batched(
+            || {
+                let mut screen = Screen::default();
+                bytes_written(&mut screen, before.clone());
+                screen
+            },
+            |mut screen| bytes_written(&mut screen, after.clone()),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_keystroke);
+criterion_main!(benches);
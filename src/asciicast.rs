@@ -0,0 +1,257 @@
+//! Recording what a `Repl` writes to its sink, as well as the events it
+//! receives, as an asciicast v2 file, so that rendering bugs can be
+//! reproduced offline with e.g. `asciinema play`.
+
+use camino::Utf8Path;
+use crate::error::ReplBlockResult;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// A handle to an asciicast v2 recording.  Clones share the recording, also
+/// across threads.
+/// Hand it to `ReplBuilder::asciicast()` to record a session.
+#[derive(Clone, Debug)]
+pub struct Asciicast {
+    recording: Arc<Mutex<Recording>>,
+}
+
+#[derive(Debug)]
+struct Recording {
+    file: BufWriter<File>,
+    start: Instant,
+    /// The trailing bytes of the output that don't form a complete UTF-8
+    /// sequence yet
+    partial: Vec<u8>,
+    /// Whether the last byte of output was a `\r`
+    after_cr: bool,
+}
+
+impl Asciicast {
+    /// Create the file at `filepath`, and write the header of the recording
+    /// to it, including the current size of the terminal.
+    pub fn create(filepath: impl AsRef<Utf8Path>) -> ReplBlockResult<Self> {
+        let (width, height) = terminal::size().unwrap_or((80, 24));
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_secs())
+            .unwrap_or_default();
+        let env: serde_json::Map<String, serde_json::Value> = ["SHELL", "TERM"].iter()
+            .filter_map(|&name| Some((name.to_string(), std::env::var(name).ok()?.into())))
+            .collect();
+        let header = serde_json::json!({
+            "version": 2,
+            "width": width,
+            "height": height,
+            "timestamp": timestamp,
+            "env": env,
+        });
+        let mut file = BufWriter::new(File::create(filepath.as_ref())?);
+        writeln!(file, "{header}")?;
+        file.flush()?;
+        Ok(Self {
+            recording: Arc::new(Mutex::new(Recording {
+                file,
+                start: Instant::now(),
+                partial: vec![],
+                after_cr: false,
+            })),
+        })
+    }
+
+    fn recording(&self) -> MutexGuard<'_, Recording> {
+        self.recording.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record `bytes` as output.  If it was written outside of raw mode,
+    /// i.e. it's `cooked`, the terminal translated `\n` to `\r\n`, so a
+    /// player needs that done in the recording.
+    pub(crate) fn record_output(&self, bytes: &[u8], cooked: bool) -> std::io::Result<()> {
+        let mut recording = self.recording();
+        recording.partial.extend_from_slice(bytes);
+        let valid_len = match std::str::from_utf8(&recording.partial) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => recording.partial.len(), // Invalid rather than incomplete
+        };
+        let bytes: Vec<u8> = recording.partial.drain(..valid_len).collect();
+        let mut data = String::with_capacity(bytes.len());
+        for c in String::from_utf8_lossy(&bytes).chars() {
+            if cooked && c == '\n' && !recording.after_cr {
+                data.push('\r');
+            }
+            recording.after_cr = c == '\r';
+            data.push(c);
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        recording.write_event("o", &data)
+    }
+
+    /// Record an `event` that was read from the terminal: a resize, or
+    /// input that's encoded as the bytes the terminal would have sent.
+    pub(crate) fn record_event(&self, event: &Event) -> std::io::Result<()> {
+        let mut recording = self.recording();
+        match event {
+            Event::Resize(width, height) => {
+                recording.write_event("r", &format!("{width}x{height}"))
+            }
+            Event::Key(key) => match encode_key(key) {
+                Some(data) => recording.write_event("i", &data),
+                None => Ok(()),
+            },
+            Event::Paste(text) => {
+                recording.write_event("i", &format!("\x1b[200~{text}\x1b[201~"))
+            }
+            Event::FocusGained | Event::FocusLost | Event::Mouse(_) => Ok(()),
+        }
+    }
+}
+
+impl Recording {
+    fn write_event(&mut self, code: &str, data: &str) -> std::io::Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+        let event = serde_json::json!([time, code, data]);
+        writeln!(self.file, "{event}")?;
+        self.file.flush()
+    }
+}
+
+/// Encode `key` as the bytes a terminal would send for it, or return `None`
+/// for keys that don't have a common encoding, or that were released.
+fn encode_key(key: &KeyEvent) -> Option<String> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    let encoded = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            match c.to_ascii_lowercase() {
+                c @ 'a'..='z' => char::from(c as u8 - b'a' + 1).to_string(),
+                ' ' | '@' => "\0".to_string(),
+                _ => return None,
+            }
+        }
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Enter => "\r".to_string(),
+        KeyCode::Tab => "\t".to_string(),
+        KeyCode::BackTab => "\x1b[Z".to_string(),
+        KeyCode::Backspace => "\x7f".to_string(),
+        KeyCode::Esc => "\x1b".to_string(),
+        KeyCode::Up => "\x1b[A".to_string(),
+        KeyCode::Down => "\x1b[B".to_string(),
+        KeyCode::Right => "\x1b[C".to_string(),
+        KeyCode::Left => "\x1b[D".to_string(),
+        KeyCode::Home => "\x1b[H".to_string(),
+        KeyCode::End => "\x1b[F".to_string(),
+        KeyCode::Insert => "\x1b[2~".to_string(),
+        KeyCode::Delete => "\x1b[3~".to_string(),
+        KeyCode::PageUp => "\x1b[5~".to_string(),
+        KeyCode::PageDown => "\x1b[6~".to_string(),
+        _ => return None,
+    };
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        Some(format!("\x1b{encoded}"))
+    } else {
+        Some(encoded)
+    }
+}
+
+/// A sink that writes to another sink `W`, and records everything that's
+/// written to it in an `Asciicast`.  Output is recorded when the sink is
+/// flushed, so that e.g. a rendered frame is recorded as a single event.
+#[derive(Debug)]
+pub struct AsciicastSink<W: Write> {
+    sink: W,
+    /// The recording, or `None` to just pass the output on to `sink`
+    asciicast: Option<Asciicast>,
+    /// The output written since the last flush
+    pending: Vec<u8>,
+}
+
+impl<W: Write> AsciicastSink<W> {
+    pub fn new(sink: W, asciicast: Asciicast) -> Self {
+        Self::wrap(sink, Some(asciicast))
+    }
+
+    /// Wrap `sink`, recording to `asciicast` if there is one.
+    pub(crate) fn wrap(sink: W, asciicast: Option<Asciicast>) -> Self {
+        Self { sink, asciicast, pending: vec![] }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.sink
+    }
+}
+
+impl<W: Write> Write for AsciicastSink<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.sink.write(buf)?;
+        if self.asciicast.is_some() {
+            self.pending.extend_from_slice(&buf[..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some(asciicast) = &self.asciicast {
+            let cooked = !terminal::is_raw_mode_enabled().unwrap_or(false);
+            asciicast.record_output(&self.pending, cooked)?;
+            self.pending.clear();
+        }
+        self.sink.flush()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn record_output_and_events() -> ReplBlockResult<()> {
+        let path = std::env::temp_dir()
+            .join(format!("repl-block-test-{}.cast", std::process::id()));
+        let path = camino::Utf8PathBuf::try_from(path)?;
+        let asciicast = Asciicast::create(&path)?;
+        let mut sink = AsciicastSink::new(vec![], asciicast.clone());
+        // "é" is split over two writes
+        sink.write_all(b"a\n")?;
+        sink.write_all(b"\xc3")?;
+        sink.flush()?;
+        sink.write_all(b"\xa9\r\n")?;
+        sink.flush()?;
+        asciicast.record_output(b"raw\n", false)?;
+        asciicast.record_event(&Event::Resize(100, 30))?;
+        let ctrl_t = KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL);
+        asciicast.record_event(&Event::Key(ctrl_t))?;
+        let alt_left = KeyEvent::new(KeyCode::Left, KeyModifiers::ALT);
+        asciicast.record_event(&Event::Key(alt_left))?;
+        assert_eq!(sink.sink, "a\né\r\n".as_bytes());
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path)?;
+        let lines: Vec<serde_json::Value> = contents.lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines[0]["version"], 2);
+        let events: Vec<(&str, &str)> = lines[1..].iter()
+            .map(|event| (event[1].as_str().unwrap(), event[2].as_str().unwrap()))
+            .collect();
+        assert_eq!(events, [
+            ("o", "a\r\n"),
+            ("o", "é\r\n"),
+            ("o", "raw\n"),
+            ("r", "100x30"),
+            ("i", "\x14"),
+            ("i", "\x1b\x1b[D"),
+        ]);
+        Ok(())
+    }
+}
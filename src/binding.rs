@@ -0,0 +1,70 @@
+//! Custom key bindings, which let the host application edit the cmd.
+
+use crate::cmd::Cmd;
+use crate::error::ReplBlockResult;
+use crate::repl::Coords;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub(crate) type KeyAction<'eval> =
+    dyn FnMut(&mut Cmd, &mut Coords) -> ReplBlockResult<()> + 'eval;
+
+/// A key binding registered by the host application.
+pub(crate) struct KeyBinding<'eval> {
+    pub(crate) key: KeyEvent,
+    pub(crate) description: String,
+    pub(crate) action: Box<KeyAction<'eval>>,
+}
+
+impl<'eval> KeyBinding<'eval> {
+    /// Return whether `key` triggers `self`.  Only the key code and the
+    /// modifiers are compared.
+    pub(crate) fn matches(&self, key: &KeyEvent) -> bool {
+        self.key.code == key.code && self.key.modifiers == key.modifiers
+    }
+}
+
+impl<'eval> std::fmt::Debug for KeyBinding<'eval> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyBinding")
+            .field("key", &self.key)
+            .field("description", &self.description)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Describe `key` the way keys are described in the help for the key
+/// bindings, e.g. `Ctrl-T` or `Alt-Shift-Left`.
+pub(crate) fn describe(key: &KeyEvent) -> String {
+    let mut description = String::new();
+    for (modifier, name) in [
+        (KeyModifiers::CONTROL, "Ctrl-"),
+        (KeyModifiers::ALT, "Alt-"),
+        (KeyModifiers::SHIFT, "Shift-"),
+    ] {
+        if key.modifiers.contains(modifier) {
+            description.push_str(name);
+        }
+    }
+    match key.code {
+        KeyCode::Char(' ') => description.push_str("Space"),
+        KeyCode::Char(c) => description.extend(c.to_uppercase()),
+        KeyCode::F(n) => description.push_str(&format!("F{n}")),
+        code => description.push_str(&format!("{code:?}")),
+    }
+    description
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describe_keys() {
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+        assert_eq!(describe(&key(KeyCode::Char('t'), KeyModifiers::CONTROL)), "Ctrl-T");
+        assert_eq!(describe(&key(KeyCode::Char(' '), KeyModifiers::CONTROL)), "Ctrl-Space");
+        assert_eq!(describe(&key(KeyCode::Left, KeyModifiers::ALT | KeyModifiers::SHIFT)), "Alt-Shift-Left");
+        assert_eq!(describe(&key(KeyCode::F(5), KeyModifiers::NONE)), "F5");
+    }
+}
@@ -0,0 +1,119 @@
+//! Bracket matching and auto-pairing of brackets and quotes.
+
+use crate::cmd::Cmd;
+use crate::repl::Coords;
+
+/// The pairs of brackets that are matched and auto-paired.
+const BRACKETS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+
+/// The quotes that are auto-paired.
+const QUOTES: [&str; 2] = ["\"", "'"];
+
+/// Return the closing bracket for `opener`, if it is an opening bracket.
+pub(crate) fn closer_of(opener: &str) -> Option<&'static str> {
+    BRACKETS.iter()
+        .find(|(open, _)| *open == opener)
+        .map(|(_, close)| *close)
+}
+
+/// Return the opening bracket for `closer`, if it is a closing bracket.
+pub(crate) fn opener_of(closer: &str) -> Option<&'static str> {
+    BRACKETS.iter()
+        .find(|(_, close)| *close == closer)
+        .map(|(open, _)| *open)
+}
+
+pub(crate) fn is_quote(grapheme: &str) -> bool {
+    QUOTES.contains(&grapheme)
+}
+
+/// Return the grapheme at `pos` in `cmd`, if any.
+pub(crate) fn grapheme_at(cmd: &Cmd, pos: Coords) -> Option<&str> {
+    cmd.lines().get(pos.y as usize)?.graphemes().nth(pos.x as usize)
+}
+
+/// Return the grapheme just before `pos` in `cmd` on the same `Line`, if any.
+pub(crate) fn grapheme_before(cmd: &Cmd, pos: Coords) -> Option<&str> {
+    let x = pos.x.checked_sub(1)?;
+    grapheme_at(cmd, Coords { x, y: pos.y })
+}
+
+/// Find a pair of matching brackets near `cursor`.
+/// The bracket under the cursor takes precedence over the one before it.
+/// Return the positions of both brackets, in the order in which they were
+/// found i.e. the bracket near the cursor first.
+pub(crate) fn find_matching_pair(cmd: &Cmd, cursor: Coords) -> Option<(Coords, Coords)> {
+    let candidates = [
+        Some(cursor),
+        cursor.x.checked_sub(1).map(|x| Coords { x, y: cursor.y }),
+    ];
+    candidates.into_iter()
+        .flatten()
+        .find_map(|pos| Some((pos, find_match(cmd, pos)?)))
+}
+
+/// Find the bracket matching the one at `pos`, which may be located on a
+/// different `Line` of `cmd`.  Return `None` if there is no bracket at
+/// `pos`, or if it is unbalanced.
+pub(crate) fn find_match(cmd: &Cmd, pos: Coords) -> Option<Coords> {
+    let bracket = grapheme_at(cmd, pos)?;
+    if let Some(closer) = closer_of(bracket) {
+        // Scan forwards
+        let mut depth = 0usize;
+        for (y, line) in cmd.lines().iter().enumerate().skip(pos.y as usize) {
+            let skip = if y == pos.y as usize { pos.x as usize } else { 0 };
+            for (x, g) in line.graphemes().enumerate().skip(skip) {
+                if g == bracket {
+                    depth += 1;
+                } else if g == closer {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(Coords { x: x as u16, y: y as u16 });
+                    }
+                }
+            }
+        }
+        None
+    } else if let Some(opener) = opener_of(bracket) {
+        // Scan backwards
+        let mut depth = 0usize;
+        for (y, line) in cmd.lines().iter().enumerate().take(pos.y as usize + 1).rev() {
+            let graphemes: Vec<&str> = line.graphemes().collect();
+            let end = if y == pos.y as usize { pos.x as usize + 1 } else { graphemes.len() };
+            for (x, g) in graphemes[..end].iter().enumerate().rev() {
+                if *g == bracket {
+                    depth += 1;
+                } else if *g == opener {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(Coords { x: x as u16, y: y as u16 });
+                    }
+                }
+            }
+        }
+        None
+    } else {
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn match_brackets_across_lines() {
+        let cmd = Cmd::from_source_code("foo(bar[0],\n  {baz})\n)");
+        let at = |x, y| Coords { x, y };
+        assert_eq!(find_match(&cmd, at(3, 0)), Some(at(7, 1)));
+        assert_eq!(find_match(&cmd, at(7, 1)), Some(at(3, 0)));
+        assert_eq!(find_match(&cmd, at(7, 0)), Some(at(9, 0)));
+        assert_eq!(find_match(&cmd, at(2, 1)), Some(at(6, 1)));
+        assert_eq!(find_match(&cmd, at(0, 2)), None);
+        assert_eq!(find_match(&cmd, at(0, 0)), None);
+        // The bracket under the cursor takes precedence
+        assert_eq!(find_matching_pair(&cmd, at(8, 1)), Some((at(7, 1), at(3, 0))));
+        assert_eq!(find_matching_pair(&cmd, at(6, 1)), Some((at(6, 1), at(2, 1))));
+    }
+}
@@ -0,0 +1,230 @@
+//! Detection of the capabilities of the terminal, and degradation of colors
+//! and other styling to what the terminal actually supports.
+
+use crossterm::style::{Color, ContentStyle};
+
+/// The colors that a terminal is able to display.
+/// The variants are ordered from least to most capable.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ColorSupport {
+    /// No colors at all
+    None,
+    /// The 16 basic ANSI colors
+    Ansi16,
+    /// The 256 colors of the xterm palette
+    Ansi256,
+    /// 24-bit RGB colors
+    TrueColor,
+}
+
+/// What a terminal is capable of, as far as a `Repl` is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TermCapabilities {
+    pub colors: ColorSupport,
+    /// Whether the terminal is dumb i.e. `TERM=dumb`, in which case
+    /// styling, cursor shapes and scroll sequences are not emitted.
+    pub dumb: bool,
+}
+
+impl Default for TermCapabilities {
+    fn default() -> Self {
+        Self { colors: ColorSupport::TrueColor, dumb: false }
+    }
+}
+
+impl TermCapabilities {
+    /// Detect the capabilities of the terminal from the environment,
+    /// i.e. the `TERM`, `COLORTERM` and `NO_COLOR` environment variables.
+    pub fn detect() -> Self {
+        let var = |name: &str| std::env::var(name).ok();
+        let no_color = var("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let caps = Self::from_vars(var("TERM").as_deref(), var("COLORTERM").as_deref());
+        if no_color {
+            Self { colors: ColorSupport::None, ..caps }
+        } else {
+            caps
+        }
+    }
+
+    /// Determine the capabilities of a terminal from the values of the
+    /// `TERM` and `COLORTERM` environment variables.
+    pub fn from_vars(term: Option<&str>, colorterm: Option<&str>) -> Self {
+        let term = term.unwrap_or("").to_ascii_lowercase();
+        let colorterm = colorterm.unwrap_or("").to_ascii_lowercase();
+        if term == "dumb" {
+            return Self { colors: ColorSupport::None, dumb: true };
+        }
+        let colors = if colorterm == "truecolor" || colorterm == "24bit"
+            || term.contains("truecolor") || term.contains("direct")
+        {
+            ColorSupport::TrueColor
+        } else if term.contains("256color") {
+            ColorSupport::Ansi256
+        } else if cfg!(windows) && term.is_empty() {
+            // Modern Windows consoles support RGB colors but don't set TERM
+            ColorSupport::TrueColor
+        } else {
+            ColorSupport::Ansi16
+        };
+        Self { colors, dumb: false }
+    }
+
+    /// Downgrade `color` to one that the terminal can display,
+    /// or return `None` if the terminal doesn't support colors.
+    pub fn downgrade_color(&self, color: Color) -> Option<Color> {
+        match (self.colors, color) {
+            (_, Color::Reset) => Some(Color::Reset),
+            (ColorSupport::None, _) => None,
+            (ColorSupport::TrueColor, color) => Some(color),
+            (ColorSupport::Ansi256, Color::Rgb { r, g, b }) => {
+                Some(Color::AnsiValue(rgb_to_ansi256(r, g, b)))
+            }
+            (ColorSupport::Ansi256, color) => Some(color),
+            (ColorSupport::Ansi16, Color::Rgb { r, g, b }) => {
+                Some(nearest_ansi16((r, g, b)))
+            }
+            (ColorSupport::Ansi16, Color::AnsiValue(value)) => {
+                Some(nearest_ansi16(ansi256_to_rgb(value)))
+            }
+            (ColorSupport::Ansi16, color) => Some(color),
+        }
+    }
+
+    /// Downgrade `style` to one that the terminal can display.
+    /// On dumb terminals, all styling is removed.
+    pub fn downgrade_style(&self, style: ContentStyle) -> ContentStyle {
+        if self.dumb {
+            return ContentStyle::new();
+        }
+        ContentStyle {
+            foreground_color: style.foreground_color
+                .and_then(|color| self.downgrade_color(color)),
+            background_color: style.background_color
+                .and_then(|color| self.downgrade_color(color)),
+            underline_color: style.underline_color
+                .and_then(|color| self.downgrade_color(color)),
+            attributes: style.attributes,
+        }
+    }
+
+    /// Return whether the cursor shape can be changed.
+    pub(crate) fn supports_cursor_styles(&self) -> bool {
+        !self.dumb
+    }
+
+    /// Return whether the terminal supports scroll sequences.
+    pub(crate) fn supports_scrolling(&self) -> bool {
+        !self.dumb
+    }
+}
+
+/// The RGB values of the 16 basic ANSI colors, as used by xterm.
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black,       (0x00, 0x00, 0x00)),
+    (Color::DarkRed,     (0xcd, 0x00, 0x00)),
+    (Color::DarkGreen,   (0x00, 0xcd, 0x00)),
+    (Color::DarkYellow,  (0xcd, 0xcd, 0x00)),
+    (Color::DarkBlue,    (0x00, 0x00, 0xee)),
+    (Color::DarkMagenta, (0xcd, 0x00, 0xcd)),
+    (Color::DarkCyan,    (0x00, 0xcd, 0xcd)),
+    (Color::Grey,        (0xe5, 0xe5, 0xe5)),
+    (Color::DarkGrey,    (0x7f, 0x7f, 0x7f)),
+    (Color::Red,         (0xff, 0x00, 0x00)),
+    (Color::Green,       (0x00, 0xff, 0x00)),
+    (Color::Yellow,      (0xff, 0xff, 0x00)),
+    (Color::Blue,        (0x5c, 0x5c, 0xff)),
+    (Color::Magenta,     (0xff, 0x00, 0xff)),
+    (Color::Cyan,        (0x00, 0xff, 0xff)),
+    (Color::White,       (0xff, 0xff, 0xff)),
+];
+
+/// The levels of each of the components of the 6x6x6 color cube
+/// of the xterm 256-color palette.
+const CUBE_LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+fn distance((r0, g0, b0): (u8, u8, u8), (r1, g1, b1): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r0, r1) + d(g0, g1) + d(b0, b1)
+}
+
+fn nearest_ansi16(rgb: (u8, u8, u8)) -> Color {
+    ANSI16.iter()
+        .min_by_key(|(_, ansi)| distance(rgb, *ansi))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+fn rgb_to_ansi256(r: u8, g: u8, b: u8) -> u8 {
+    let cube_idx = |c: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&idx| (CUBE_LEVELS[idx] as i32 - c as i32).abs())
+            .unwrap_or(0) as u8
+    };
+    let (ri, gi, bi) = (cube_idx(r), cube_idx(g), cube_idx(b));
+    let cube_value = 16 + 36 * ri + 6 * gi + bi;
+    let cube_rgb = (CUBE_LEVELS[ri as usize], CUBE_LEVELS[gi as usize], CUBE_LEVELS[bi as usize]);
+
+    // The grayscale ramp runs from 0x08 to 0xee in steps of 10
+    let avg = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+    let gray_idx = (avg.saturating_sub(8) / 10).min(23);
+    let gray_value = 232 + gray_idx;
+    let gray_level = 8 + 10 * gray_idx;
+    let gray_rgb = (gray_level, gray_level, gray_level);
+
+    if distance((r, g, b), gray_rgb) < distance((r, g, b), cube_rgb) {
+        gray_value
+    } else {
+        cube_value
+    }
+}
+
+fn ansi256_to_rgb(value: u8) -> (u8, u8, u8) {
+    match value {
+        0..=15 => ANSI16[value as usize].1,
+        16..=231 => {
+            let idx = value - 16;
+            (
+                CUBE_LEVELS[(idx / 36) as usize],
+                CUBE_LEVELS[(idx / 6 % 6) as usize],
+                CUBE_LEVELS[(idx % 6) as usize],
+            )
+        }
+        232..=255 => {
+            let level = 8 + 10 * (value - 232);
+            (level, level, level)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_and_downgrade_colors() {
+        let caps = |term, colorterm| TermCapabilities::from_vars(term, colorterm);
+        assert_eq!(caps(Some("xterm-256color"), Some("truecolor")).colors, ColorSupport::TrueColor);
+        assert_eq!(caps(Some("xterm-256color"), None).colors, ColorSupport::Ansi256);
+        assert_eq!(caps(Some("screen"), None).colors, ColorSupport::Ansi16);
+        assert_eq!(caps(Some("dumb"), Some("truecolor")), TermCapabilities {
+            colors: ColorSupport::None,
+            dumb: true,
+        });
+
+        let orange = Color::Rgb { r: 0xff, g: 0x87, b: 0x00 };
+        let ansi256 = TermCapabilities { colors: ColorSupport::Ansi256, dumb: false };
+        assert_eq!(ansi256.downgrade_color(orange), Some(Color::AnsiValue(208)));
+        let gray = Color::Rgb { r: 0x80, g: 0x80, b: 0x80 };
+        assert_eq!(ansi256.downgrade_color(gray), Some(Color::AnsiValue(244)));
+
+        let ansi16 = TermCapabilities { colors: ColorSupport::Ansi16, dumb: false };
+        assert_eq!(ansi16.downgrade_color(Color::AnsiValue(196)), Some(Color::Red));
+        assert_eq!(ansi16.downgrade_color(Color::Rgb { r: 0, g: 0, b: 10 }), Some(Color::Black));
+        assert_eq!(ansi16.downgrade_color(Color::Cyan), Some(Color::Cyan));
+
+        let none = TermCapabilities { colors: ColorSupport::None, dumb: false };
+        assert_eq!(none.downgrade_color(Color::Cyan), None);
+        assert_eq!(none.downgrade_color(Color::Reset), Some(Color::Reset));
+    }
+}
@@ -0,0 +1,249 @@
+//! Capturing what's written to stdout, e.g. by the evaluator, while it's
+//! still being shown on the terminal.
+
+use std::io::Write;
+
+/// While a `StdoutCapture` is alive, whatever is written to the stdout file
+/// descriptor is collected, and also passed on to the terminal.
+/// This works at the level of file descriptors, so that output written by
+/// other means than `print!()`, e.g. by a child process, is captured too.
+/// When stdout is a terminal, output is captured through a pseudo-terminal,
+/// so that the evaluator still writes to a terminal.  When stderr is the
+/// same file as stdout, it's captured along with stdout, so that the order
+/// of their output is kept.
+/// Capturing is only supported on Unix; elsewhere nothing is captured.
+pub(crate) struct StdoutCapture {
+    #[cfg(unix)]
+    inner: Option<unix::Inner>,
+}
+
+/// Receives each chunk of output as soon as it's captured, on another thread.
+pub(crate) type Tee = Box<dyn FnMut(&[u8]) + Send>;
+
+impl StdoutCapture {
+    /// Start capturing.  If there's a `tee`, it receives the output as it
+    /// arrives, including any output that arrives after capturing stopped.
+    pub(crate) fn start(tee: Option<Tee>) -> std::io::Result<Self> {
+        std::io::stdout().flush()?;
+        std::io::stderr().flush()?;
+        #[cfg(unix)]
+        {
+            Ok(Self { inner: Some(unix::Inner::start(tee)?) })
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = tee;
+            Ok(Self {})
+        }
+    }
+
+    /// Stop capturing, and return the captured output, or `None` if
+    /// capturing isn't supported on this platform.
+    pub(crate) fn finish(mut self) -> std::io::Result<Option<String>> {
+        std::io::stdout().flush()?;
+        std::io::stderr().flush()?;
+        #[cfg(unix)]
+        if let Some(inner) = self.inner.take() {
+            let bytes = inner.finish()?;
+            return Ok(Some(String::from_utf8_lossy(&bytes).into_owned()));
+        }
+        Ok(None)
+    }
+}
+
+impl Drop for StdoutCapture {
+    /// Restore stdout, even when unwinding from a panic in the evaluator.
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(inner) = self.inner.take() {
+            let _ = std::io::stdout().flush();
+            let _ = std::io::stderr().flush();
+            let _ = inner.finish();
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{FromRawFd, RawFd};
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+    use super::Tee;
+
+    /// How long to wait for the output that's still underway once capturing
+    /// stops.  Processes that outlive the evaluator, e.g. ones that run in
+    /// the background, may keep writing to the capture for much longer.
+    const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+    /// The output that was captured so far, or `None` once capturing stopped
+    type Captured = Arc<Mutex<Option<Vec<u8>>>>;
+
+    pub(super) struct Inner {
+        /// A duplicate of the original stdout
+        saved: RawFd,
+        /// A duplicate of the original stderr, if that's captured too
+        saved_err: Option<RawFd>,
+        captured: Captured,
+        /// Copies the output from the capture to the original stdout
+        copier: JoinHandle<()>,
+    }
+
+    fn check(ret: libc::c_int) -> std::io::Result<libc::c_int> {
+        if ret < 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    impl Inner {
+        pub(super) fn start(tee: Option<Tee>) -> std::io::Result<Self> {
+            // SAFETY: Only fds that were just obtained are used.  Each fd is
+            //         owned by exactly one `File`, or closed explicitly.
+            unsafe {
+                let saved = check(libc::dup(libc::STDOUT_FILENO))?;
+                let pair = if libc::isatty(saved) == 1 {
+                    open_pty(saved)
+                } else {
+                    open_pipe()
+                };
+                let (reader, writer) = match pair {
+                    Ok(pair) => pair,
+                    Err(err) => {
+                        libc::close(saved);
+                        return Err(err);
+                    }
+                };
+                let saved_err = if is_same_file(libc::STDOUT_FILENO, libc::STDERR_FILENO) {
+                    Some(check(libc::dup(libc::STDERR_FILENO))?)
+                } else {
+                    None
+                };
+                let echo = File::from_raw_fd(check(libc::dup(saved))?);
+                check(libc::dup2(writer, libc::STDOUT_FILENO))?;
+                if saved_err.is_some() {
+                    check(libc::dup2(writer, libc::STDERR_FILENO))?;
+                }
+                libc::close(writer);
+                let reader = File::from_raw_fd(reader);
+                let captured: Captured = Arc::new(Mutex::new(Some(vec![])));
+                let copier = {
+                    let captured = captured.clone();
+                    std::thread::spawn(move || copy(reader, echo, captured, tee))
+                };
+                Ok(Self { saved, saved_err, captured, copier })
+            }
+        }
+
+        /// Point stdout (and stderr) at the original files again, which
+        /// closes the capture, and collect the output that went through it.
+        /// Output that's written by processes that still hold the capture
+        /// after `DRAIN_TIMEOUT` is passed on to the terminal, but isn't
+        /// collected.
+        pub(super) fn finish(self) -> std::io::Result<Vec<u8>> {
+            // SAFETY: `saved` and `saved_err` are valid fds owned by `self`.
+            unsafe {
+                check(libc::dup2(self.saved, libc::STDOUT_FILENO))?;
+                libc::close(self.saved);
+                if let Some(saved_err) = self.saved_err {
+                    check(libc::dup2(saved_err, libc::STDERR_FILENO))?;
+                    libc::close(saved_err);
+                }
+            }
+            let deadline = Instant::now() + DRAIN_TIMEOUT;
+            while !self.copier.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            if self.copier.is_finished() {
+                self.copier.join()
+                    .map_err(|_| std::io::Error::other("the stdout copier panicked"))?;
+            }
+            let captured = self.captured.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take();
+            Ok(captured.unwrap_or_default())
+        }
+    }
+
+    /// Open a pipe, and return its (reader, writer) fds.
+    unsafe fn open_pipe() -> std::io::Result<(RawFd, RawFd)> {
+        let mut fds = [0; 2];
+        check(libc::pipe(fds.as_mut_ptr()))?;
+        Ok((fds[0], fds[1]))
+    }
+
+    /// Open a pseudo-terminal that mirrors the size and settings of the
+    /// terminal `tty`, and return its (master, slave) fds.
+    /// Output processing is turned off, so that what's read from the master
+    /// is exactly what was written to the slave.
+    unsafe fn open_pty(tty: RawFd) -> std::io::Result<(RawFd, RawFd)> {
+        let master = check(libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY))?;
+        let slave = (|| {
+            check(libc::grantpt(master))?;
+            check(libc::unlockpt(master))?;
+            // NOTE: `ptsname()` isn't thread-safe, but `ptsname_r()` isn't
+            //       available everywhere.
+            let name = libc::ptsname(master);
+            if name.is_null() {
+                return Err(std::io::Error::last_os_error());
+            }
+            check(libc::open(name, libc::O_RDWR | libc::O_NOCTTY))
+        })();
+        let slave = match slave {
+            Ok(slave) => slave,
+            Err(err) => {
+                libc::close(master);
+                return Err(err);
+            }
+        };
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(tty, &mut termios) == 0 {
+            termios.c_oflag &= !libc::OPOST;
+            libc::tcsetattr(slave, libc::TCSANOW, &termios);
+        }
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(tty, libc::TIOCGWINSZ, &mut size) == 0 {
+            libc::ioctl(slave, libc::TIOCSWINSZ, &size);
+        }
+        Ok((master, slave))
+    }
+
+    /// Return whether the fds `a` and `b` refer to the same file.
+    unsafe fn is_same_file(a: RawFd, b: RawFd) -> bool {
+        let mut a_stat: libc::stat = std::mem::zeroed();
+        let mut b_stat: libc::stat = std::mem::zeroed();
+        libc::fstat(a, &mut a_stat) == 0
+            && libc::fstat(b, &mut b_stat) == 0
+            && (a_stat.st_dev, a_stat.st_ino) == (b_stat.st_dev, b_stat.st_ino)
+    }
+
+    /// Copy what's read from `reader` to `echo` and `tee`, collecting it in
+    /// `captured` until capturing stops.  This returns once all writers
+    /// closed the capture, which a pseudo-terminal reports as an error.
+    fn copy(mut reader: File, mut echo: File, captured: Captured, mut tee: Option<Tee>) {
+        let mut buf = [0; 4096];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => {
+                    if let Some(captured) = captured.lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .as_mut()
+                    {
+                        captured.extend_from_slice(&buf[..n]);
+                    }
+                    let _ = echo.write_all(&buf[..n]);
+                    let _ = echo.flush();
+                    if let Some(tee) = &mut tee {
+                        tee(&buf[..n]);
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(_) => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Clipboards, which hold the text that is cut or copied in a `Repl`.
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// A `Clipboard` stores the text that's cut or copied in a `Repl`,
+/// and provides the text that's pasted with `Ctrl-Y`.
+pub trait Clipboard {
+    /// Store `text` on the clipboard.  The `sink` of the `Repl` is provided
+    /// for clipboards that are controlled by the terminal.
+    fn set_contents(&mut self, text: &str, sink: &mut dyn Write) -> std::io::Result<()>;
+
+    /// Return the text on the clipboard, if any.
+    fn contents(&mut self) -> Option<String>;
+}
+
+/// A `Clipboard` that copies text to the system clipboard of the terminal
+/// using OSC 52 escape sequences, which also works over SSH.
+/// Since few terminals allow reading the system clipboard, pasting with
+/// `Ctrl-Y` uses the text that was last copied with this clipboard instead.
+/// Text copied elsewhere is pasted through the terminal (bracketed paste).
+#[derive(Clone, Debug, Default)]
+pub struct Osc52Clipboard {
+    last_copied: Option<String>,
+}
+
+impl Osc52Clipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Clipboard for Osc52Clipboard {
+    fn set_contents(&mut self, text: &str, sink: &mut dyn Write) -> std::io::Result<()> {
+        write!(sink, "{}", osc52(text))?;
+        sink.flush()?;
+        self.last_copied = Some(text.to_string());
+        Ok(())
+    }
+
+    fn contents(&mut self) -> Option<String> {
+        self.last_copied.clone()
+    }
+}
+
+/// A `Clipboard` that keeps its contents in memory, e.g. for testing.
+/// Clones share their contents, so that the contents can be inspected after
+/// a clone has been handed to a `Repl`.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryClipboard {
+    contents: Rc<RefCell<Option<String>>>,
+}
+
+impl InMemoryClipboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the text on the clipboard, if any.
+    pub fn get(&self) -> Option<String> {
+        self.contents.borrow().clone()
+    }
+
+    /// Put `text` on the clipboard.
+    pub fn set(&self, text: impl Into<String>) {
+        *self.contents.borrow_mut() = Some(text.into());
+    }
+}
+
+impl Clipboard for InMemoryClipboard {
+    fn set_contents(&mut self, text: &str, _sink: &mut dyn Write) -> std::io::Result<()> {
+        self.set(text);
+        Ok(())
+    }
+
+    fn contents(&mut self) -> Option<String> {
+        self.get()
+    }
+}
+
+/// Return the OSC 52 escape sequence that sets the system clipboard
+/// to `text`.
+pub(crate) fn osc52(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+/// Encode `bytes` as standard, padded base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                let sextet = (n >> (18 - 6 * idx)) & 0x3f;
+                encoded.push(ALPHABET[sextet as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn copy_to_clipboards() -> std::io::Result<()> {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64("λx.x".as_bytes()), "zrt4Lng=");
+
+        let mut sink = vec![];
+        let mut osc52 = Osc52Clipboard::new();
+        osc52.set_contents("foo", &mut sink)?;
+        assert_eq!(sink, b"\x1b]52;c;Zm9v\x07");
+        assert_eq!(osc52.contents(), Some("foo".to_string()));
+
+        let clipboard = InMemoryClipboard::new();
+        let mut handle = clipboard.clone();
+        handle.set_contents("bar", &mut std::io::sink())?;
+        assert_eq!(clipboard.get(), Some("bar".to_string()));
+        Ok(())
+    }
+}
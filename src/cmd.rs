@@ -1,9 +1,17 @@
-//!
+//! The `Cmd` and `Line` types that make up the contents of the input area.
 
 use crate::repl::{Coords, ORIGIN};
+use crate::screen;
+use itertools::Itertools;
 use unicode_segmentation::UnicodeSegmentation;
 
 
+/// A cmd i.e. the text that is edited in the input area, and evaluated once
+/// it's entered.  A `Cmd` consists of one or more `Line`s.
+///
+/// Positions in a `Cmd` are `Coords`, in which `y` is the index of a `Line`
+/// and `x` is the index of a grapheme in that `Line`.  Methods that take
+/// positions clamp them to the `Cmd` first, see `Cmd::clamp_pos()`.
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Cmd { lines: Vec<Line> }
@@ -15,30 +23,125 @@ impl Default for Cmd {
 }
 
 impl Cmd {
+    /// Create a `Cmd` from `source` code, with one `Line` per line of `source`.
+    /// This is the inverse of `Cmd::text()`, but not of
+    /// `Cmd::to_source_code()`, which drops empty `Line`s.
+    pub fn from_source_code(source: &str) -> Self {
+        Self {
+            lines: source.split('\n')
+                .map(Line::from)
+                .collect()
+        }
+    }
+
+    /// Return the number of `Line`s in `self`.
     pub fn count_lines(&self) -> u16 {
         self.lines.len() as u16
     }
 
+    /// Return whether `self` has no `Line`s at all.  Note that a default
+    /// `Cmd` consists of a single, empty `Line`.
     pub fn is_empty(&self) -> bool {
         self.lines.is_empty()
     }
 
+    /// Return the `Line` at index `y`, or `None` if there is no such `Line`.
+    /// Unlike indexing, this doesn't panic.
+    pub fn line(&self, y: u16) -> Option<&Line> {
+        self.lines.get(y as usize)
+    }
+
+    /// Return the `Line` at index `y` mutably, or `None` if there is no such
+    /// `Line`.  Unlike indexing, this doesn't panic.
+    pub fn line_mut(&mut self, y: u16) -> Option<&mut Line> {
+        self.lines.get_mut(y as usize)
+    }
+
+    /// Insert `c` at a given `pos`ition.
     pub fn insert_char(&mut self, pos: Coords, c: char) {
         if self.lines.is_empty() {
             self.lines.push(Line::new_start());
         }
+        let pos = self.clamp_pos(pos);
         self[pos.y].insert_char(pos.x, c);
     }
 
+    /// Insert `s` at a given `pos`ition, splitting it over multiple `Line`s
+    /// if it contains newlines.  Return the position right after `s`.
+    pub fn insert_str(&mut self, mut pos: Coords, s: &str) -> Coords {
+        if self.lines.is_empty() {
+            self.lines.push(Line::new_start());
+        }
+        pos = self.clamp_pos(pos);
+        for (idx, part) in s.split('\n').enumerate() {
+            if idx > 0 {
+                self.insert_empty_line(pos);
+                pos = Coords { x: ORIGIN.x, y: pos.y + 1 };
+            }
+            self[pos.y].insert_str(pos.x, part);
+            pos.x += part.graphemes(true).count() as u16;
+        }
+        pos
+    }
+
+    /// Return the text between the positions `start` and `end`, in either
+    /// order.  Lines are separated by newlines.
+    pub fn text_between(&self, start: Coords, end: Coords) -> String {
+        if self.is_empty() {
+            return String::new();
+        }
+        let (start, end) = self.clamp_range(start, end);
+        (start.y..=end.y)
+            .map(|y| {
+                let line = &self[y];
+                let from = if y == start.y { start.x as usize } else { 0 };
+                let to = if y == end.y { end.x as usize } else { usize::MAX };
+                line.graphemes().take(to).skip(from).collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Remove the text between the positions `start` and `end`, in either
+    /// order.  The `Line`s of `start` and `end` are joined.
+    pub fn remove_range(&mut self, start: Coords, end: Coords) {
+        if self.is_empty() {
+            return; // nothing to remove
+        }
+        let (start, end) = self.clamp_range(start, end);
+        if start.y == end.y {
+            self[start.y].remove_range(start.x, end.x);
+            return;
+        }
+        let tail = self[end.y].split_off(end.x);
+        self[start.y].split_off(start.x);
+        self[start.y].push_str(&tail.to_string());
+        self.lines.drain(start.y as usize + 1 ..= end.y as usize);
+    }
+
+    /// Replace the text between the positions `start` and `end`, in either
+    /// order, with `text`.  Return the position right after the inserted text.
+    pub fn replace_range(&mut self, start: Coords, end: Coords, text: &str) -> Coords {
+        let (start, end) = self.clamp_range(start, end);
+        self.remove_range(start, end);
+        self.insert_str(start, text)
+    }
+
+    /// Clamp the positions `a` and `b`, and return them in order.
+    fn clamp_range(&self, a: Coords, b: Coords) -> (Coords, Coords) {
+        let (a, b) = (self.clamp_pos(a), self.clamp_pos(b));
+        if (a.y, a.x) <= (b.y, b.x) { (a, b) } else { (b, a) }
+    }
+
+    /// Split the `Line` at a given `pos`ition in two, moving the graphemes
+    /// from `pos` onwards to a new `Line` right after it.
     pub fn insert_empty_line(&mut self, pos: Coords) {
-        self.lines.insert(pos.y as usize + 1, Line {
-            content: self[pos.y].graphemes().skip(pos.x as usize).collect(),
-            kind: LineKind::Start,
-        });
-        self[pos.y] = Line {
-            content: self[pos.y].graphemes().take(pos.x as usize).collect(),
-            kind: self[pos.y].kind,
-        };
+        if self.lines.is_empty() {
+            self.lines.push(Line::new_start());
+        }
+        let pos = self.clamp_pos(pos);
+        let tail = self[pos.y].split_off(pos.x);
+        self.lines.insert(pos.y as usize + 1, tail);
     }
 
     /// Remove the grapheme before a given `pos`ition.
@@ -46,13 +149,14 @@ impl Cmd {
         if self.is_empty() {
             return; // nothing to remove
         }
+        let pos = self.clamp_pos(pos);
         if pos.y == 0 && pos.x == 0 {
             // NOP
         } else if pos.y == 0 && pos.x > 0 {
             self[pos.y].rm_grapheme_before(pos.x);
         } else if pos.y > 0 && pos.x == 0 {
             let removed: Line = self.lines.remove(pos.y as usize);
-            self[pos.y - 1].push_str(removed.as_str());
+            self[pos.y - 1].push_str(&removed.to_string());
         } else if pos.y > 0 && pos.x > 0 {
             self[pos.y].rm_grapheme_before(pos.x);
         } else {
@@ -66,11 +170,12 @@ impl Cmd {
         if self.is_empty() {
             return; // nothing to remove
         }
+        let pos = self.clamp_pos(pos);
         let is_end_of_line = pos.x == self[pos.y].count_graphemes();
         let has_next_line = pos.y + 1 < self.count_lines();
         if is_end_of_line && has_next_line {
             let removed: Line = self.lines.remove(pos.y as usize + 1);
-            self[pos.y].push_str(removed.as_str());
+            self[pos.y].push_str(&removed.to_string());
         } else if is_end_of_line && !has_next_line {
             // NOP
         } else if !is_end_of_line {
@@ -85,6 +190,49 @@ impl Cmd {
         self.lines.as_slice()
     }
 
+    /// Return the text of `self`, with its `Line`s separated by newlines.
+    /// Unlike `Cmd::to_source_code()`, this retains empty `Line`s, so that
+    /// byte offsets in the text can be converted to positions in `self` with
+    /// `Cmd::coords_of()`, and back with `Cmd::byte_offset_of()`.
+    pub fn text(&self) -> String {
+        self.lines.iter().join("\n")
+    }
+
+    /// Return the position of the grapheme that contains the byte at
+    /// `offset` in `Cmd::text()`.  An offset at a newline maps to the end of
+    /// the preceding `Line`, and offsets past the end to the end of `self`.
+    pub fn coords_of(&self, mut offset: usize) -> Coords {
+        for (y, line) in self.lines.iter().enumerate() {
+            if offset <= line.len() {
+                return Coords { x: line.grapheme_idx_of(offset), y: y as u16 };
+            }
+            offset -= line.len() + 1; // Skip the newline
+        }
+        self.end_of_cmd()
+    }
+
+    /// Return the byte offset in `Cmd::text()` of the grapheme at `pos`.
+    /// Positions outside of `self` are clamped first.
+    pub fn byte_offset_of(&self, pos: Coords) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let pos = self.clamp_pos(pos);
+        let preceding: usize = self.lines[..pos.y as usize].iter()
+            .map(|line| line.len() + 1)
+            .sum();
+        preceding + self[pos.y].byte_offset_of(pos.x)
+    }
+
+    /// Return the position in `self` that is closest to `pos`.
+    pub fn clamp_pos(&self, pos: Coords) -> Coords {
+        let Some(max_y) = self.max_line_idx() else {
+            return ORIGIN;
+        };
+        let y = std::cmp::min(pos.y, max_y as u16);
+        Coords { x: std::cmp::min(pos.x, self[y].count_graphemes()), y }
+    }
+
     #[cfg(test)]
     // Compression here means that all line continuations (which exist for the
     // purpose of line overflow rendering) have been merged with their starting
@@ -101,7 +249,7 @@ impl Cmd {
                 clines.push(line.clone());
             } else if line.kind == LineKind::Overflow {
                 let prev = clines.last_mut().unwrap();
-                prev.push_str(line.as_str());
+                prev.push_str(&line.to_string());
             } else {
                 unreachable!();
             }
@@ -128,16 +276,104 @@ impl Cmd {
         &self,
         // The width (in columns) of the repl
         repl_width: u16,
-        // The length of the prompt
-        prompt_len: u16,
+        // The length of the prompt on the first line
+        default_prompt_len: u16,
+        // The length of the prompt on continuation lines
+        continue_prompt_len: u16,
+        // The width (in columns) of a tab stop
+        tab_width: u16,
     ) -> Self {
         Self {
             lines: self.lines().iter()
-                .flat_map(|line| line.uncompress(repl_width, prompt_len))
+                .enumerate()
+                .flat_map(|(lidx, line)| {
+                    let prompt_len = if lidx == 0 {
+                        default_prompt_len
+                    } else {
+                        continue_prompt_len
+                    };
+                    line.uncompress(repl_width, prompt_len, tab_width)
+                })
                 .collect()
         }
     }
 
+    /// Map a `cursor` position in `self` to the corresponding position in
+    /// the uncompressed version of `self`, where `x` is a column on screen
+    /// (including the prompt) and `y` is the index of an uncompressed line.
+    pub(crate) fn uncompress_cursor(
+        &self,
+        cursor: Coords,
+        // The width (in columns) of the repl
+        repl_width: u16,
+        // The length of the prompt on the first line
+        default_prompt_len: u16,
+        // The length of the prompt on continuation lines
+        continue_prompt_len: u16,
+        // The width (in columns) of a tab stop
+        tab_width: u16,
+    ) -> Coords {
+        let prompt_len = |y: usize| {
+            if y == 0 { default_prompt_len } else { continue_prompt_len }
+        };
+        let mut uncursor = ORIGIN;
+        for (lidx, line) in self.lines().iter().enumerate() {
+            let layout = line.layout(repl_width, prompt_len(lidx), tab_width);
+            if lidx == cursor.y as usize {
+                let pos = layout.get(cursor.x as usize)
+                    .or(layout.last())
+                    .copied()
+                    .unwrap_or(ORIGIN);
+                return Coords { x: pos.x, y: uncursor.y + pos.y };
+            }
+            uncursor.y += layout.last().map(|pos| pos.y + 1).unwrap_or(1);
+        }
+        uncursor
+    }
+
+    /// Map a position in the uncompressed version of `self`, where `x` is a
+    /// column on screen (including the prompt) and `y` is the index of an
+    /// uncompressed line, back to a cursor position in `self`.
+    /// This is the inverse of `Cmd::uncompress_cursor()`.
+    pub(crate) fn compress_cursor(
+        &self,
+        uncursor: Coords,
+        // The width (in columns) of the repl
+        repl_width: u16,
+        // The length of the prompt on the first line
+        default_prompt_len: u16,
+        // The length of the prompt on continuation lines
+        continue_prompt_len: u16,
+        // The width (in columns) of a tab stop
+        tab_width: u16,
+    ) -> Coords {
+        let prompt_len = |y: usize| {
+            if y == 0 { default_prompt_len } else { continue_prompt_len }
+        };
+        let mut first_unline = 0;
+        for (lidx, line) in self.lines().iter().enumerate() {
+            let layout = line.layout(repl_width, prompt_len(lidx), tab_width);
+            let num_unlines = layout.last().map(|pos| pos.y + 1).unwrap_or(1);
+            let is_last_line = lidx + 1 == self.lines().len();
+            if uncursor.y < first_unline + num_unlines || is_last_line {
+                let row = std::cmp::min(uncursor.y - first_unline, num_unlines - 1);
+                let on_row = || layout.iter().enumerate().filter(|(_, pos)| pos.y == row);
+                // Pick the grapheme that covers the column, or the first one
+                // on the row if the column is located in the prompt
+                let x = on_row()
+                    .rev()
+                    .find(|(_, pos)| pos.x <= uncursor.x)
+                    .or_else(|| on_row().next())
+                    .map(|(gidx, _)| gidx as u16)
+                    .unwrap_or(0);
+                return Coords { x, y: lidx as u16 };
+            }
+            first_unline += num_unlines;
+        }
+        ORIGIN
+    }
+
+    /// Return the index of the last `Line`, if any.
     pub fn max_line_idx(&self) -> Option<usize> {
         let num_lines = self.count_lines() as usize;
         if num_lines > 0 {
@@ -147,6 +383,7 @@ impl Cmd {
         }
     }
 
+    /// Return the position right after the last grapheme of `self`.
     pub fn end_of_cmd(&self) -> Coords {
         self.lines.last()
             .map(|last| Coords {
@@ -156,14 +393,12 @@ impl Cmd {
             .unwrap_or(ORIGIN)
     }
 
+    /// Return the source code of `self` i.e. its non-empty `Line`s,
+    /// separated by newlines.
     pub fn to_source_code(&self) -> String {
-        use itertools::Itertools;
-        #[allow(unstable_name_collisions)] // for the .intersperse() call below
         self.lines.iter()
             .filter(|line| !line.is_empty())
-            .map(Line::as_str)
-            .intersperse("\n")
-            .collect()
+            .join("\n")
     }
 
 }
@@ -242,98 +477,178 @@ impl std::ops::IndexMut<Last> for Cmd {
 }
 
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-#[derive(serde::Deserialize, serde::Serialize)]
-#[derive(derive_more::From)]
-#[serde(transparent)]
+/// A single line of a `Cmd`.  The text is stored in `Chunk`s that cache the
+/// grapheme boundaries, so that editing long lines doesn't require
+/// segmenting the whole line into graphemes over and over again.
+/// The cumulative size of the chunks is kept as well, so that the chunk
+/// containing a grapheme or byte can be found with a binary search.
+#[derive(Clone)]
 pub struct Line {
-    content: String,
-    #[serde(skip)]
-    // #[serde(skip_deserializing)]
-    #[serde(default)]
+    /// The text of the line, split at grapheme boundaries.
+    /// None of the chunks are empty.
+    chunks: Vec<Chunk>,
+    /// The end of each chunk, in graphemes and in bytes since the start
+    /// of `self`
+    ends: Vec<ChunkEnd>,
     pub(crate) kind: LineKind,
 }
 
-impl Line {
-    const CAPACITY: usize = 200;
+/// A position in a `Line` in graphemes, and in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct ChunkEnd {
+    graphemes: usize,
+    bytes: usize,
+}
 
+impl Line {
     fn new(kind: LineKind) -> Self {
-        Self {
-            content: String::with_capacity(Self::CAPACITY),
-            kind
+        Self::from_chunks(kind, vec![])
+    }
+
+    fn from_chunks(kind: LineKind, chunks: Vec<Chunk>) -> Self {
+        let mut line = Self { chunks, ends: vec![], kind };
+        line.update_ends(0);
+        line
+    }
+
+    /// Recompute the ends of the chunks from the chunk at `cidx` onwards.
+    fn update_ends(&mut self, cidx: usize) {
+        self.ends.truncate(cidx);
+        let mut end = self.start_of_chunk(cidx);
+        for chunk in &self.chunks[cidx..] {
+            end.graphemes += chunk.len();
+            end.bytes += chunk.text.len();
+            self.ends.push(end);
+        }
+    }
+
+    /// Return where the chunk at `cidx` starts.
+    fn start_of_chunk(&self, cidx: usize) -> ChunkEnd {
+        match cidx {
+            0 => ChunkEnd::default(),
+            _ => self.ends[cidx - 1],
         }
     }
 
+    /// Return the index of the chunk that contains the grapheme at `gidx`,
+    /// if any.
+    fn chunk_of_grapheme(&self, gidx: usize) -> Option<usize> {
+        let cidx = self.ends.partition_point(|end| end.graphemes <= gidx);
+        (cidx < self.chunks.len()).then_some(cidx)
+    }
+
     pub(crate) fn new_start() -> Self {
         Self::new(LineKind::Start)
     }
 
-    pub(crate) fn new_overflow() -> Self {
-        Self::new(LineKind::Overflow)
+    /// Create a `Line` out of `graphemes` that are known to be complete,
+    /// without segmenting their concatenation again.
+    fn from_graphemes<'g>(kind: LineKind, graphemes: impl IntoIterator<Item = &'g str>) -> Self {
+        let mut chunks: Vec<Chunk> = vec![];
+        for grapheme in graphemes {
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() < Chunk::MAX_LEN => chunk.push(grapheme),
+                _ => chunks.push(Chunk::from_graphemes([grapheme])),
+            }
+        }
+        Self::from_chunks(kind, chunks)
     }
 
+    /// Return whether `self` starts a line of the cmd, rather than being the
+    /// continuation of a line that overflows the width of the terminal.
     pub fn is_start(&self) -> bool {
         self.kind == LineKind::Start
     }
 
     pub fn is_empty(&self) -> bool {
-        self.content.is_empty()
+        self.chunks.is_empty()
+    }
+
+    /// Return the length of `self` in bytes.
+    pub fn len(&self) -> usize {
+        self.ends.last().map_or(0, |end| end.bytes)
     }
 
+    /// Insert `c` before the grapheme at `x_pos`.
     pub fn insert_char(&mut self, x_pos: u16, c: char) {
-        let mut graphemes = self.graphemes();
-        let mut content = String::new();
-        for _ in 0..x_pos {
-            let Some(g) = graphemes.next() else { break };
-            content.push_str(g);
-        }
-        content.push(c);
-        while let Some(g) = graphemes.next() {
-            content.push_str(g);
-        }
-        drop(graphemes);
-        self.content = content;
+        self.insert_str(x_pos, c.encode_utf8(&mut [0; 4]));
     }
 
+    /// Insert `s` before the grapheme at `x_pos`.
     pub fn insert_str(&mut self, x_pos: u16, s: &str) {
-        let mut graphemes = self.graphemes();
-        let mut content = String::new();
-        for _ in 0..x_pos {
-            let Some(g) = graphemes.next() else { break };
-            content.push_str(g);
-        }
-        content.push_str(s);
-        while let Some(g) = graphemes.next() {
-            content.push_str(g);
-        }
-        drop(graphemes);
-        self.content = content;
+        self.splice(x_pos as usize, x_pos as usize, s);
     }
 
-    pub fn graphemes(&self) -> impl Iterator<Item = &str> + '_ {
-        self.content.graphemes(true)
+    pub fn graphemes(&self) -> impl DoubleEndedIterator<Item = &str> + '_ {
+        self.chunks.iter().flat_map(Chunk::graphemes)
     }
 
+    /// Return the graphemes of `self`, together with their byte offsets.
     pub fn grapheme_indices(&self) -> impl Iterator<Item = (usize, &str)> {
-        self.content.grapheme_indices(true)
+        let mut chunk_offset = 0;
+        self.chunks.iter().flat_map(move |chunk| {
+            let offset = chunk_offset;
+            chunk_offset += chunk.text.len();
+            chunk.grapheme_indices().map(move |(start, g)| (offset + start, g))
+        })
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.text.chars())
+    }
+
+    fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.chunks.iter().flat_map(|chunk| chunk.text.bytes())
+    }
+
+    /// Return the index of the grapheme that contains the byte at `offset`,
+    /// or the number of graphemes if `offset` is at or past the end.
+    pub fn grapheme_idx_of(&self, offset: usize) -> u16 {
+        let cidx = self.ends.partition_point(|end| end.bytes <= offset);
+        let Some(chunk) = self.chunks.get(cidx) else {
+            return self.count_graphemes();
+        };
+        let start = self.start_of_chunk(cidx);
+        let offset = offset - start.bytes;
+        let within = chunk.starts.partition_point(|&start| start as usize <= offset);
+        (start.graphemes + within - 1) as u16
+    }
+
+    /// Return the byte offset of the grapheme at `x_pos`, or the length
+    /// of `self` in bytes if `x_pos` is at or past the end.
+    pub fn byte_offset_of(&self, x_pos: u16) -> usize {
+        let Some(cidx) = self.chunk_of_grapheme(x_pos as usize) else {
+            return self.len();
+        };
+        let start = self.start_of_chunk(cidx);
+        start.bytes + self.chunks[cidx].starts[x_pos as usize - start.graphemes] as usize
     }
 
+    #[allow(dead_code)]
     pub fn max_x(&self) -> u16 {
         self.count_graphemes().saturating_sub(1)
     }
 
+    /// Return the number of graphemes in `self`.
     pub fn count_graphemes(&self) -> u16 {
-        self.content.graphemes(true).count() as _
+        self.ends.last().map_or(0, |end| end.graphemes) as u16
     }
 
-    pub fn push_str(&mut self, s: &str) {
-        self.content.push_str(s);
+    /// Replace the leading whitespace of `self` with `indentation`.
+    pub fn set_indentation(&mut self, indentation: &str) {
+        let indented = self.graphemes()
+            .take_while(|g| g.chars().all(char::is_whitespace))
+            .count();
+        self.splice(0, indented, indentation);
     }
 
-    pub fn as_str(&self) -> &str {
-        self.content.as_str()
+    /// Append `s` to `self`.
+    pub fn push_str(&mut self, s: &str) {
+        let end = self.count_graphemes() as usize;
+        self.splice(end, end, s);
     }
 
+    /// Remove the grapheme before `xpos`, if any.
     pub fn rm_grapheme_before(&mut self, xpos: u16) {
         if xpos == 0 {
             return; // No graphemes to remove
@@ -341,14 +656,101 @@ impl Line {
         self.rm_grapheme_at(xpos - 1);
     }
 
+    /// Remove the grapheme at `xpos`, if any.
     pub fn rm_grapheme_at(&mut self, xpos: u16) {
-        *self = Self {
-            content: self.graphemes().enumerate()
-                .filter(|&(gidx, _)| gidx != xpos as usize)
-                .map(|(_, grapheme)| grapheme)
-                .collect(),
-            kind: self.kind,
-        };
+        self.remove_range(xpos, xpos + 1);
+    }
+
+    /// Remove the graphemes from `start` up to (but not including) `end`.
+    pub fn remove_range(&mut self, start: u16, end: u16) {
+        self.splice(start as usize, end as usize, "");
+    }
+
+    /// Split `self` in two at `x_pos`.  Return the graphemes from `x_pos`
+    /// onwards as a new `Line`, which starts a line of the cmd.
+    pub fn split_off(&mut self, x_pos: u16) -> Self {
+        let tail = Self::from_graphemes(LineKind::Start, self.graphemes().skip(x_pos as usize));
+        let end = self.count_graphemes();
+        self.remove_range(x_pos, end);
+        tail
+    }
+
+    /// Replace the graphemes from `start` up to (but not including) `end`
+    /// with `s`.  Only the chunks around the edit are segmented again, as the
+    /// edit may change the grapheme boundaries around it e.g. when a
+    /// combining character is inserted.
+    fn splice(&mut self, start: usize, end: usize, s: &str) {
+        let len = self.count_graphemes() as usize;
+        let start = std::cmp::min(start, len);
+        let end = std::cmp::min(std::cmp::max(start, end), len);
+        if self.chunks.is_empty() {
+            *self = Self::from_chunks(self.kind, Chunk::segment(s));
+            return;
+        }
+        // Find the chunks containing `start` and `end`, and widen that range
+        // with the neighbouring chunks
+        let max_cidx = self.chunks.len() - 1;
+        let chunk_ending_at_or_after = |gidx: usize| std::cmp::min(
+            self.ends.partition_point(|chunk_end| chunk_end.graphemes < gidx),
+            max_cidx,
+        );
+        let first = chunk_ending_at_or_after(start).saturating_sub(1);
+        let last = std::cmp::min(chunk_ending_at_or_after(end) + 1, max_cidx);
+        let first_start = self.start_of_chunk(first).graphemes;
+        let mut text = String::new();
+        let graphemes = self.chunks[first..=last].iter().flat_map(Chunk::graphemes);
+        for (gidx, g) in (first_start..).zip(graphemes) {
+            if gidx == start {
+                text.push_str(s);
+            }
+            if !(start..end).contains(&gidx) {
+                text.push_str(g);
+            }
+        }
+        if start == self.ends[last].graphemes {
+            text.push_str(s); // Append
+        }
+        self.chunks.splice(first..=last, Chunk::segment(&text));
+        self.update_ends(first);
+    }
+
+    /// Lay out the graphemes of `self` on screen.  Return the position of
+    /// each grapheme, followed by the position right after the last one.
+    /// In each position, `x` is a column on screen (including the prompt)
+    /// and `y` is the index of an uncompressed line of `self`.
+    /// Tab stops are counted from the start of `self`, excluding the prompt.
+    pub(crate) fn layout(
+        &self,
+        // The width (in columns) of the repl
+        repl_width: u16,
+        // The length of the prompt
+        prompt_len: u16,
+        // The width (in columns) of a tab stop
+        tab_width: u16,
+    ) -> Vec<Coords> {
+        let start = if self.is_start() { prompt_len } else { 0 };
+        let mut pos = Coords { x: start, y: 0 };
+        // The column within `self`, which determines the tab stops
+        let mut col = 0;
+        let mut positions = Vec::with_capacity(self.count_graphemes() as usize + 1);
+        for g in self.graphemes() {
+            let width = display_width(g, col, tab_width);
+            if pos.x + width > repl_width && pos.x > 0 {
+                // Overflow onto the next uncompressed line
+                pos = Coords { x: 0, y: pos.y + 1 };
+            }
+            positions.push(pos);
+            pos.x += width;
+            col += width;
+        }
+        positions.push(pos);
+        positions
+    }
+
+    /// Return the width (in columns) of `self` when it's displayed starting
+    /// at column `col`.
+    pub(crate) fn display_width(&self, col: u16, tab_width: u16) -> u16 {
+        self.graphemes().fold(col, |x, g| x + display_width(g, x, tab_width)) - col
     }
 
     pub(crate) fn uncompress(
@@ -357,48 +759,249 @@ impl Line {
         repl_width: u16,
         // The length of the prompt
         prompt_len: u16,
+        // The width (in columns) of a tab stop
+        tab_width: u16,
     ) -> Vec<Self> {
-        if self.is_empty() {
-            return vec![Line::new(self.kind)];
+        let layout = self.layout(repl_width, prompt_len, tab_width);
+        let num_ulines = layout.last().map(|pos| pos.y + 1).unwrap_or(1);
+        let mut ugraphemes: Vec<Vec<&str>> = vec![vec![]; num_ulines as usize];
+        for (g, pos) in self.graphemes().zip(layout) {
+            ugraphemes[pos.y as usize].push(g);
         }
+        ugraphemes.into_iter()
+            .enumerate()
+            .map(|(y, graphemes)| {
+                let kind = if y == 0 { self.kind } else { LineKind::Overflow };
+                Line::from_graphemes(kind, graphemes)
+            })
+            .collect()
+    }
 
-        let mut ulines = vec![];
-        let mut graphemes = self.graphemes().peekable();
+}
 
-        let mut lline0 = Line::new(self.kind);
-        let start = if self.is_start() { prompt_len } else { 0 };
-        for _ in start..repl_width {
-            let Some(g) = graphemes.next() else { break };
-            lline0.push_str(g);
+impl From<&str> for Line {
+    fn from(s: &str) -> Self {
+        Self::from_chunks(LineKind::Start, Chunk::segment(s))
+    }
+}
+
+impl From<String> for Line {
+    fn from(s: String) -> Self {
+        Self::from(s.as_str())
+    }
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for chunk in &self.chunks {
+            write!(f, "{}", chunk.text)?;
         }
-        if !lline0.is_empty() {
-            ulines.push(lline0);
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Line")
+            .field("content", &self.to_string())
+            .field("kind", &self.kind)
+            .finish()
+    }
+}
+
+impl PartialEq for Line {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.bytes().eq(other.bytes())
+    }
+}
+
+impl Eq for Line {}
+
+impl PartialOrd for Line {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Line {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.bytes().cmp(other.bytes()).then(self.kind.cmp(&other.kind))
+    }
+}
+
+impl std::hash::Hash for Line {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        for chunk in &self.chunks {
+            state.write(chunk.text.as_bytes());
         }
+        state.write_u8(0xff);
+        self.kind.hash(state);
+    }
+}
+
+/// `Line`s are (de)serialized as plain strings.
+impl serde::Serialize for Line {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Line {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Line::from)
+    }
+}
+
+/// A piece of the text of a `Line` that starts and ends at grapheme
+/// boundaries, along with the offsets at which its graphemes start.
+#[derive(Clone, Debug, Default)]
+struct Chunk {
+    text: String,
+    /// The byte offset in `text` of each grapheme
+    starts: Vec<u32>,
+}
 
-        'continue_lines: loop {
-            let mut lline = Line::new_overflow();
-            for _ in 0 .. repl_width {
-                let Some(g) = graphemes.next() else { break };
-                lline.push_str(g);
+impl Chunk {
+    /// The maximum number of graphemes in a `Chunk`
+    const MAX_LEN: usize = 128;
+
+    /// Split `text` into graphemes, and those into evenly sized `Chunk`s.
+    fn segment(text: &str) -> Vec<Self> {
+        let graphemes: Vec<&str> = text.graphemes(true).collect();
+        let num_chunks = graphemes.len().div_ceil(Self::MAX_LEN);
+        if num_chunks == 0 {
+            return vec![];
+        }
+        let chunk_len = graphemes.len().div_ceil(num_chunks);
+        graphemes.chunks(chunk_len)
+            .map(|graphemes| Self::from_graphemes(graphemes.iter().copied()))
+            .collect()
+    }
+
+    fn from_graphemes<'g>(graphemes: impl IntoIterator<Item = &'g str>) -> Self {
+        let mut chunk = Self::default();
+        for grapheme in graphemes {
+            chunk.push(grapheme);
+        }
+        chunk
+    }
+
+    fn push(&mut self, grapheme: &str) {
+        self.starts.push(self.text.len() as u32);
+        self.text.push_str(grapheme);
+    }
+
+    /// Return the number of graphemes in `self`.
+    fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    fn grapheme_indices(&self) -> impl DoubleEndedIterator<Item = (usize, &str)> + '_ {
+        (0..self.len()).map(|gidx| {
+            let start = self.starts[gidx] as usize;
+            let end = self.starts.get(gidx + 1).map_or(self.text.len(), |&end| end as usize);
+            (start, &self.text[start..end])
+        })
+    }
+
+    fn graphemes(&self) -> impl DoubleEndedIterator<Item = &str> + '_ {
+        self.grapheme_indices().map(|(_, g)| g)
+    }
+}
+
+/// How tab characters are handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tabs {
+    /// Insert spaces up to the next tab stop instead of tab characters.
+    /// Tab stops are located at multiples of the given width.
+    Expand(u16),
+    /// Keep tab characters, and render them as whitespace up to the next tab
+    /// stop.  Tab stops are located at multiples of the given width.
+    /// In both cases, columns are counted from the start of a line of the
+    /// cmd, i.e. the prompt isn't taken into account.
+    Render(u16),
+}
+
+impl Default for Tabs {
+    fn default() -> Self {
+        Self::Render(8)
+    }
+}
+
+impl Tabs {
+    /// Return the distance (in columns) between tab stops.
+    pub fn width(&self) -> u16 {
+        match *self {
+            Self::Expand(width) | Self::Render(width) => std::cmp::max(1, width),
+        }
+    }
+
+    /// Expand the tabs in `s`, unless tabs are to be kept as they are.
+    /// Tab stops are counted from the start of `s`, offset by `col` columns.
+    pub(crate) fn expand<'s>(&self, s: &'s str, col: u16) -> std::borrow::Cow<'s, str> {
+        if matches!(self, Self::Render(_)) || !s.contains('\t') {
+            return s.into();
+        }
+        let mut expanded = String::with_capacity(s.len());
+        let mut x = col;
+        for g in s.graphemes(true) {
+            if g == "\n" {
+                x = 0;
+                expanded.push_str(g);
+                continue;
             }
-            if !lline.is_empty() {
-                ulines.push(lline);
+            let width = display_width(g, x, self.width());
+            if g == "\t" {
+                expanded.extend(std::iter::repeat(' ').take(width as usize));
+            } else {
+                expanded.push_str(g);
             }
+            x += width;
+        }
+        expanded.into()
+    }
+}
 
-            if graphemes.peek().is_none() {
-                break 'continue_lines;
-            }
+/// Return the width (in columns) of `grapheme` when it's displayed at
+/// column `col`.  Tabs extend up to the next tab stop, control characters
+/// are displayed in caret notation e.g. `^C`, and wide graphemes e.g. CJK
+/// characters occupy 2 columns.
+pub(crate) fn display_width(grapheme: &str, col: u16, tab_width: u16) -> u16 {
+    match grapheme {
+        "\t" => {
+            let tab_width = std::cmp::max(1, tab_width);
+            tab_width - col % tab_width
         }
+        g if is_control(g) => caret_notation(g).chars().count() as u16,
+        g => screen::grapheme_width(g),
+    }
+}
 
-        ulines
+/// Return the text that's displayed for `grapheme` at column `col`.
+pub(crate) fn display_grapheme(grapheme: &str, col: u16, tab_width: u16) -> std::borrow::Cow<'_, str> {
+    match grapheme {
+        "\t" => " ".repeat(display_width(grapheme, col, tab_width) as usize).into(),
+        g if is_control(g) => caret_notation(g).into(),
+        g => g.into(),
     }
+}
 
+fn is_control(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(char::is_control)
 }
 
-impl std::fmt::Display for Line {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.content)
-    }
+/// Render the control characters in `grapheme` in caret notation, e.g.
+/// `^C` for ETX and `^?` for DEL.  C1 control characters are prefixed with
+/// `M-`, as with `cat -v`.
+fn caret_notation(grapheme: &str) -> String {
+    grapheme.chars()
+        .map(|c| match c as u32 {
+            code @ 0x00..=0x1f => format!("^{}", char::from(code as u8 + 0x40)),
+            0x7f => "^?".to_string(),
+            code @ 0x80..=0x9f => format!("M-^{}", char::from(code as u8 - 0x40)),
+            _ => c.to_string(),
+        })
+        .collect()
 }
 
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -421,8 +1024,8 @@ mod test {
             lines: vec![
                 Line {
                     // This line is intentionally very long without line breaks.
-                    content: r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&amp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#.to_string(),
                     kind: LineKind::Start,
+                    ..Line::from(r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&amp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#)
                 }
             ]
         };
@@ -430,19 +1033,20 @@ mod test {
         // Plausible, but otherwise unspecial values:
         let term_cols = 100;
         let prompt_len = 3;
+        let tab_width = 8;
 
-        let uclines = cmd.uncompress(term_cols, prompt_len);
+        let uclines = cmd.uncompress(term_cols, prompt_len, prompt_len, tab_width);
         println!("uclines={uclines:#?}");
         let ucmd = Cmd {
             lines: vec![
                 Line {
                     // length == term_cols- prompt_len
-                    content: r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&a"#.to_string(),
                     kind: LineKind::Start,
+                    ..Line::from(r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&a"#)
                 },
                 Line {
-                    content: r#"mp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#.to_string(),
                     kind: LineKind::Overflow,
+                    ..Line::from(r#"mp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#)
                 }
             ]
         };
@@ -455,27 +1059,27 @@ mod test {
             lines: vec![
                 Line {
                     // This line is intentionally very long without line breaks.
-                    content: r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&amp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#.to_string(),
                     kind: LineKind::Start,
+                    ..Line::from(r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&amp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#)
                 }
             ]
         };
         assert_eq!(ccmd, clines);
         assert_eq!(ccmd, cmd);
 
-        let uclines2 = cmd.uncompress(term_cols, prompt_len);
+        let uclines2 = cmd.uncompress(term_cols, prompt_len, prompt_len, tab_width);
         println!("uclines2={uclines2:#?}");
         assert_eq!(uclines, uclines2);
         let ucmd2 = Cmd {
             lines: vec![
                 Line {
                     // length == term_cols - prompt_len
-                    content: r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&a"#.to_string(),
                     kind: LineKind::Start,
+                    ..Line::from(r#"<xml a="b">hello<?do-it a proc instr?><!--a comment-->world<kid a="b"/><![CDATA[boom bam]]>&lt;&a"#)
                 },
                 Line {
-                    content: r#"mp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#.to_string(),
                     kind: LineKind::Overflow,
+                    ..Line::from(r#"mp;&gt;&#x20;{{more text}}</xml>/descendant-or-self::processing-instruction()"#)
                 }
             ]
         };
@@ -483,4 +1087,164 @@ mod test {
 
         Ok(())
     }
+    #[test]
+    fn layout_tabs_and_control_chars() {
+        let cmd = Cmd::from_source_code("a\tb\u{3}c\nxyz\t");
+        let (width, prompt_len, tab_width) = (10, 2, 4);
+        // Tab stops are counted from the start of the line, excluding the
+        // prompt, so the tab after `a` extends up to column 4 of the line
+        let at = |x, y| Coords { x, y };
+        assert_eq!(
+            cmd[0usize].layout(width, prompt_len, tab_width),
+            vec![at(2, 0), at(3, 0), at(6, 0), at(7, 0), at(9, 0), at(10, 0)],
+        );
+        assert_eq!(
+            cmd[1usize].layout(width, prompt_len, tab_width),
+            vec![at(2, 0), at(3, 0), at(4, 0), at(5, 0), at(6, 0)],
+        );
+        assert_eq!(cmd.uncompress_cursor(at(4, 0), width, prompt_len, prompt_len, tab_width), at(9, 0));
+        assert_eq!(cmd.uncompress_cursor(at(4, 1), width, prompt_len, prompt_len, tab_width), at(6, 1));
+        assert_eq!(display_grapheme("\u{3}", 0, tab_width), "^C");
+        assert_eq!(display_grapheme("\u{7f}", 0, tab_width), "^?");
+
+        // Graphemes that don't fit anymore overflow onto the next unline
+        let narrow = cmd.uncompress(6, prompt_len, prompt_len, tab_width);
+        assert_eq!(narrow.count_lines(), 3);
+        assert_eq!(narrow[1usize].to_string(), "b\u{3}c");
+
+        assert_eq!(Tabs::Expand(4).expand("a\tbcde\tf\n\tg", 0), "a   bcde    f\n    g");
+        assert_eq!(Tabs::Render(4).expand("a\tb", 0), "a\tb");
+    }
+    #[test]
+    fn ranges_across_lines() {
+        let mut cmd = Cmd::from_source_code("foo(\n  bar,\n  baz)");
+        let at = |x, y| Coords { x, y };
+        assert_eq!(cmd.text_between(at(2, 0), at(4, 0)), "o(");
+        assert_eq!(cmd.text_between(at(4, 0), at(5, 1)), "\n  bar");
+        assert_eq!(cmd.text_between(at(3, 1), at(2, 2)), "ar,\n  ");
+        cmd.remove_range(at(4, 0), at(2, 2));
+        assert_eq!(cmd.to_source_code(), "foo(baz)");
+        cmd.remove_range(at(1, 0), at(1, 0));
+        assert_eq!(cmd.to_source_code(), "foo(baz)");
+    }
+    #[test]
+    fn offsets_and_replacement() {
+        let mut cmd = Cmd::from_source_code("let é = 1;\n\nx");
+        let at = |x, y| Coords { x, y };
+        assert_eq!(cmd.text(), "let é = 1;\n\nx");
+        assert_eq!(cmd.byte_offset_of(at(5, 0)), 6);
+        assert_eq!(cmd.coords_of(6), at(5, 0));
+        assert_eq!(cmd.coords_of(12), at(0, 1));
+        assert_eq!(cmd.byte_offset_of(at(0, 2)), 13);
+        assert_eq!(cmd.byte_offset_of(at(9, 9)), 14);
+        assert_eq!(cmd.coords_of(99), at(1, 2));
+        let end = cmd.replace_range(at(4, 0), at(0, 2), "y = 2;\nlet x");
+        assert_eq!(end, at(5, 1));
+        assert_eq!(cmd.text(), "let y = 2;\nlet xx");
+    }
+    #[test]
+    fn compress_cursor_positions() {
+        let cmd = Cmd::from_source_code("abcdefgh\nx\ty");
+        let (width, prompt_len, tab_width) = (6, 2, 4);
+        let compress = |x, y| cmd.compress_cursor(Coords { x, y }, width, prompt_len, prompt_len, tab_width);
+        let at = |x, y| Coords { x, y };
+        // "abcd" is rendered after the prompt, "efgh" on the next unline
+        assert_eq!(compress(0, 0), at(0, 0));
+        assert_eq!(compress(3, 0), at(1, 0));
+        assert_eq!(compress(2, 1), at(6, 0));
+        assert_eq!(compress(5, 1), at(8, 0));
+        // The tab covers columns 3 up to 5, and `y` overflows
+        assert_eq!(compress(3, 2), at(1, 1));
+        assert_eq!(compress(5, 2), at(1, 1));
+        assert_eq!(compress(0, 3), at(2, 1));
+        assert_eq!(compress(9, 7), at(3, 1));
+        for cursor in [at(0, 0), at(5, 0), at(8, 0), at(0, 1), at(2, 1), at(3, 1)] {
+            let uncursor = cmd.uncompress_cursor(cursor, width, prompt_len, prompt_len, tab_width);
+            assert_eq!(compress(uncursor.x, uncursor.y), cursor);
+        }
+    }
+    #[test]
+    fn edit_long_lines() {
+        let mut line = Line::from("a".repeat(300));
+        assert_eq!(line.count_graphemes(), 300);
+        assert!(line.chunks.len() > 1);
+        // A combining character joins the grapheme before it, even when
+        // that grapheme is located in another chunk
+        let boundary = line.chunks[0].len() as u16;
+        line.insert_str(boundary, "\u{301}");
+        assert_eq!(line.count_graphemes(), 300);
+        assert_eq!(line.graphemes().nth(boundary as usize - 1), Some("a\u{301}"));
+        assert_eq!(line.byte_offset_of(boundary), boundary as usize + 2);
+        assert_eq!(line.grapheme_idx_of(boundary as usize), boundary - 1);
+        assert_eq!(line.grapheme_idx_of(boundary as usize + 2), boundary);
+        line.insert_str(1, "λx");
+        line.rm_grapheme_at(0);
+        line.remove_range(250, 1000);
+        let expected = format!("λx{}a\u{301}{}", "a".repeat(boundary as usize - 2), "a".repeat(249 - boundary as usize));
+        assert_eq!(line.to_string(), expected);
+        assert_eq!(line, Line::from(expected.as_str()));
+        let tail = line.split_off(2);
+        assert_eq!((line.to_string().as_str(), tail.count_graphemes()), ("λx", 248));
+
+        // `Line`s are (de)serialized as plain strings
+        let cmd = Cmd::from_source_code("foo\n\tbar");
+        let json = serde_json::to_string(&cmd).unwrap();
+        assert_eq!(json, r#"{"lines":["foo","\tbar"]}"#);
+        assert_eq!(serde_json::from_str::<Cmd>(&json).unwrap(), cmd);
+    }
+
+    /// Check that the chunks of `line`, their cached ends, and the lookups
+    /// that use those all agree with `text`.
+    fn assert_consistent(line: &Line, text: &str) {
+        assert_eq!(line.to_string(), text);
+        assert!(line.chunks.iter().all(|chunk| !chunk.text.is_empty()));
+        let mut recomputed = line.clone();
+        recomputed.update_ends(0);
+        assert_eq!(line.ends, recomputed.ends);
+        assert_eq!(line.len(), text.len());
+        let count = text.graphemes(true).count();
+        assert_eq!(line.count_graphemes() as usize, count);
+        for (gidx, (offset, _)) in text.grapheme_indices(true).enumerate() {
+            assert_eq!(line.byte_offset_of(gidx as u16), offset);
+            assert_eq!(line.grapheme_idx_of(offset), gidx as u16);
+        }
+        assert_eq!(line.byte_offset_of(count as u16), text.len());
+        assert_eq!(line.grapheme_idx_of(text.len()), count as u16);
+    }
+
+    #[test]
+    fn edit_at_chunk_boundaries() {
+        // 8 graphemes, repeated to fill 3 chunks
+        let mut text = "αβγ de\u{301}f ".repeat(Chunk::MAX_LEN * 3 / 8);
+        let mut line = Line::from(text.as_str());
+        assert!(line.chunks.len() >= 3);
+        assert_consistent(&line, &text);
+        let boundary = line.ends[0].graphemes;
+        let end = line.count_graphemes() as usize;
+        for x in [0, boundary - 1, boundary, boundary + 1, end] {
+            let offset = text.grapheme_indices(true).nth(x).map_or(text.len(), |(o, _)| o);
+            line.insert_str(x as u16, "e\u{301}x");
+            text.insert_str(offset, "e\u{301}x");
+            assert_consistent(&line, &text);
+            line.rm_grapheme_at(x as u16);
+            text.replace_range(offset..offset + "e\u{301}".len(), "");
+            assert_consistent(&line, &text);
+        }
+    }
+
+    #[test]
+    fn out_of_range_positions_are_clamped() {
+        let far = Coords { x: 100, y: 100 };
+        let mut cmd = Cmd::from_source_code("foo\nbar");
+        cmd.insert_char(far, '!');
+        assert_eq!(cmd.to_source_code(), "foo\nbar!");
+        assert_eq!(cmd.line(1).map(Line::to_string), Some("bar!".to_string()));
+        assert_eq!(cmd.line(100), None);
+        assert_eq!(cmd.text_between(far, Coords { x: 1, y: 0 }), "oo\nbar!");
+        cmd.remove_range(far, Coords { x: 1, y: 0 });
+        assert_eq!(cmd.to_source_code(), "f");
+        cmd.rm_grapheme_at(far);
+        cmd.rm_grapheme_before(far);
+        assert_eq!(cmd.to_source_code(), "");
+    }
 }
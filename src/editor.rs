@@ -0,0 +1,98 @@
+//! Editing cmds in an external editor, i.e. `$VISUAL` or `$EDITOR`.
+
+use std::collections::hash_map::RandomState;
+use std::fs::{File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// The editor that is used when neither `$VISUAL` nor `$EDITOR` is set.
+const FALLBACK_EDITOR: &str = "vi";
+
+/// The number of names that are tried when creating a temporary file.
+const TEMP_FILE_ATTEMPTS: usize = 16;
+
+/// Write `source` to a temporary file, open that file in the user's editor
+/// and return the edited contents once the editor exits.
+/// Return `Ok(None)` if the editor exited unsuccessfully, in which case any
+/// edits are discarded.
+pub(crate) fn edit(source: &str) -> std::io::Result<Option<String>> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_EDITOR.to_string());
+    // Support editors with arguments e.g. `EDITOR="code --wait"`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(FALLBACK_EDITOR);
+
+    let (path, mut file) = create_temp_file()?;
+    let written = file.write_all(source.as_bytes()).and_then(|()| file.flush());
+    drop(file);
+    if let Err(err) = written {
+        let _ = std::fs::remove_file(&path);
+        return Err(err);
+    }
+    let status = Command::new(program).args(words).arg(&path).status();
+    let edited = match status {
+        Ok(status) if status.success() => std::fs::read_to_string(&path).map(Some),
+        Ok(_) => Ok(None),
+        Err(err) => Err(err),
+    };
+    let _ = std::fs::remove_file(&path);
+    Ok(edited?.map(|mut edited| {
+        // Most editors terminate the last line with a newline
+        if edited.ends_with('\n') {
+            edited.pop();
+            if edited.ends_with('\r') {
+                edited.pop();
+            }
+        }
+        edited
+    }))
+}
+
+/// Create a new temporary file that only the current user can access.
+/// The file name is random, and an existing file (or symlink) is never
+/// opened, so that another user can't read or redirect the cmd.
+fn create_temp_file() -> std::io::Result<(PathBuf, File)> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    for _ in 0..TEMP_FILE_ATTEMPTS {
+        // `RandomState` is randomly seeded for each instance
+        let random = RandomState::new().build_hasher().finish();
+        let filename = format!("repl-block-{}-{random:016x}.txt", std::process::id());
+        let path = std::env::temp_dir().join(filename);
+        match options.open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(std::io::Error::new(ErrorKind::AlreadyExists, "failed to create a temporary file"))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn temp_files_are_new_and_private() -> std::io::Result<()> {
+        let (path0, _) = create_temp_file()?;
+        let (path1, _) = create_temp_file()?;
+        assert_ne!(path0, path1);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path0)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(path0)?;
+        std::fs::remove_file(path1)?;
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
-//!
+//! The error type of this crate.
 
 use log::SetLoggerError;
 use serde_json::Error as SerdeJsonError;
+use toml::de::Error as TomlError;
 
 pub type ReplBlockResult<T> = std::result::Result<T, ReplBlockError>;
 
@@ -17,4 +18,6 @@ pub enum ReplBlockError {
     SetLoggerError(SetLoggerError),
     /// SerdeJsonError: {0}
     SerdeJson(SerdeJsonError),
+    /// TomlError: {0}
+    Toml(TomlError),
 }
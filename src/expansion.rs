@@ -0,0 +1,171 @@
+//! Shell-style history expansion, e.g. `!!`, `!-n`, `!n`, `!prefix` and
+//! `^old^new`.
+
+use crate::history::{History, HistIdx};
+
+pub type ExpansionResult<T> = std::result::Result<T, ExpansionError>;
+
+#[derive(Clone, Debug, PartialEq, Eq, displaydoc::Display)]
+pub enum ExpansionError {
+    /// {0}: event not found
+    EventNotFound(String),
+    /// {0}: substitution failed
+    SubstitutionFailed(String),
+}
+
+/// Expand all history references in `source` against `history`.
+/// Return `Ok(None)` if `source` doesn't contain any history references,
+/// and `Ok(Some(expanded))` otherwise.
+pub fn expand(history: &History, source: &str) -> ExpansionResult<Option<String>> {
+    if let Some(rest) = source.strip_prefix('^') {
+        return quick_substitution(history, source, rest).map(Some);
+    }
+
+    let mut expanded = String::with_capacity(source.len());
+    let mut has_expanded = false;
+    let mut in_single_quotes = false;
+    let mut chars = source.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '\'' => {
+                in_single_quotes = !in_single_quotes;
+                expanded.push(c);
+            }
+            '\\' if matches!(chars.peek(), Some((_, '!'))) => {
+                let (_, bang) = chars.next().unwrap();
+                expanded.push(c);
+                expanded.push(bang);
+            }
+            '!' if !in_single_quotes => {
+                let rest = &source[idx + c.len_utf8()..];
+                let Some((event, event_len)) = parse_event(rest) else {
+                    expanded.push(c); // Not a history reference
+                    continue;
+                };
+                let hidx = resolve_event(history, &event).ok_or_else(|| {
+                    ExpansionError::EventNotFound(format!("!{}", &rest[..event_len]))
+                })?;
+                expanded.push_str(&history[hidx].to_source_code());
+                has_expanded = true;
+                for _ in rest[..event_len].chars() {
+                    chars.next();
+                }
+            }
+            _ => expanded.push(c),
+        }
+    }
+    Ok(if has_expanded { Some(expanded) } else { None })
+}
+
+/// Handle `^old^new^`, which repeats the last cmd with the first occurrence of
+/// `old` replaced by `new`.  The trailing `^` is optional.
+fn quick_substitution(
+    history: &History,
+    source: &str,
+    rest: &str,
+) -> ExpansionResult<String> {
+    let failed = || ExpansionError::SubstitutionFailed(source.to_string());
+    let (old, new) = rest.split_once('^').unwrap_or((rest, ""));
+    let new = new.strip_suffix('^').unwrap_or(new);
+    if old.is_empty() {
+        return Err(failed());
+    }
+    let Some(last) = history.max_idx() else {
+        return Err(ExpansionError::EventNotFound("^".to_string()));
+    };
+    let last = history[last].to_source_code();
+    if !last.contains(old) {
+        return Err(failed());
+    }
+    Ok(last.replacen(old, new, 1))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Event<'s> {
+    /// `!!`
+    Last,
+    /// `!n`, where `n` is 1-based
+    Absolute(usize),
+    /// `!-n`
+    Relative(usize),
+    /// `!prefix`
+    Prefix(&'s str),
+}
+
+/// Parse the event designator following a `!`.  Return the event as well as
+/// the length in bytes of its designator, or `None` if the `!` doesn't
+/// start a history reference.
+fn parse_event(rest: &str) -> Option<(Event<'_>, usize)> {
+    let is_word_end = |c: char| c.is_whitespace() || matches!(c, '"' | '\'' | ';' | '!');
+    let digits = |s: &str| s.chars().take_while(char::is_ascii_digit).count();
+    match rest.chars().next()? {
+        '!' => Some((Event::Last, 1)),
+        '-' => {
+            let len = digits(&rest[1..]);
+            let n = rest[1..1 + len].parse().ok()?;
+            Some((Event::Relative(n), 1 + len))
+        }
+        c if c.is_ascii_digit() => {
+            let len = digits(rest);
+            let n = rest[..len].parse().ok()?;
+            Some((Event::Absolute(n), len))
+        }
+        c if is_word_end(c) || matches!(c, '=' | '(') => None,
+        _ => {
+            let len = rest.find(is_word_end).unwrap_or(rest.len());
+            Some((Event::Prefix(&rest[..len]), len))
+        }
+    }
+}
+
+fn resolve_event(history: &History, event: &Event) -> Option<HistIdx> {
+    match *event {
+        Event::Last => history.max_idx(),
+        Event::Absolute(0) | Event::Relative(0) => None,
+        Event::Absolute(n) => (n <= history.len()).then(|| HistIdx(n - 1)),
+        Event::Relative(n) => history.len().checked_sub(n).map(HistIdx),
+        Event::Prefix(prefix) => history.iter().rev(/*most recent first*/)
+            .find(|(_, cmd)| cmd.to_source_code().starts_with(prefix))
+            .map(|(hidx, _)| hidx),
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::Cmd;
+    use super::*;
+
+    fn history() -> History {
+        let mut history = History::default();
+        for src in ["ls -la", "echo hello", "cat foo.txt"] {
+            history.add_cmd(Cmd::from_source_code(src));
+        }
+        history
+    }
+
+    #[test]
+    fn expand_event_designators() {
+        let history = history();
+        let expand = |src| expand(&history, src);
+        assert_eq!(expand("!!"), Ok(Some("cat foo.txt".to_string())));
+        assert_eq!(expand("!1 | wc"), Ok(Some("ls -la | wc".to_string())));
+        assert_eq!(expand("!-2"), Ok(Some("echo hello".to_string())));
+        assert_eq!(expand("x !ec y"), Ok(Some("x echo hello y".to_string())));
+        assert_eq!(expand("^foo^bar"), Ok(Some("cat bar.txt".to_string())));
+        assert_eq!(expand("echo hi!"), Ok(None));
+        assert_eq!(expand("echo '!!' \\!!"), Ok(None));
+        assert_eq!(
+            expand("!nope"),
+            Err(ExpansionError::EventNotFound("!nope".to_string()))
+        );
+        assert_eq!(
+            expand("!4"),
+            Err(ExpansionError::EventNotFound("!4".to_string()))
+        );
+        assert_eq!(
+            expand("^baz^bar"),
+            Err(ExpansionError::SubstitutionFailed("^baz^bar".to_string()))
+        );
+    }
+}
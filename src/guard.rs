@@ -0,0 +1,139 @@
+//! Restoring the terminal when a `Repl` is dropped, returns an error or
+//! panics, so that the user isn't left with a broken terminal.
+
+use crossterm::{cursor, event, queue, terminal};
+#[cfg(not(unix))]
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+
+/// The terminal modes that a `Repl` has changed, and which need to be
+/// restored.  These are shared with the panic hook.
+#[derive(Debug, Default)]
+struct Modes {
+    /// Whether the terminal is in use by a `Repl`
+    active: AtomicBool,
+    cursor_style: AtomicBool,
+    bracketed_paste: AtomicBool,
+    mouse_capture: AtomicBool,
+}
+
+/// A guard that keeps track of the terminal modes that need restoring.
+/// The `Repl` restores them through its sink when it's dropped, and the
+/// guard installs a panic hook that restores them on the controlling
+/// terminal before the panic message is printed.
+#[derive(Debug)]
+pub(crate) struct TerminalGuard {
+    modes: Arc<Modes>,
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// The modes of the guard that was created last, for use in the panic hook.
+static LATEST_MODES: Mutex<Option<Arc<Modes>>> = Mutex::new(None);
+
+impl TerminalGuard {
+    pub(crate) fn new() -> Self {
+        let modes = Arc::new(Modes::default());
+        install_panic_hook(Arc::clone(&modes));
+        Self { modes }
+    }
+
+    /// Record that the cursor style was changed, or reset.
+    pub(crate) fn set_cursor_style(&self, changed: bool) {
+        self.modes.cursor_style.store(changed, Ordering::SeqCst);
+    }
+
+    /// Record that bracketed paste was enabled or disabled.
+    pub(crate) fn set_bracketed_paste(&self, enabled: bool) {
+        self.modes.bracketed_paste.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Record that mouse capture was enabled or disabled.
+    pub(crate) fn set_mouse_capture(&self, enabled: bool) {
+        self.modes.mouse_capture.store(enabled, Ordering::SeqCst);
+    }
+
+    /// Mark the terminal as in use by the `Repl`, when it starts or after
+    /// `restore()`.
+    pub(crate) fn rearm(&self) {
+        self.modes.active.store(true, Ordering::SeqCst);
+    }
+
+    /// Restore the terminal by writing to `sink`, which is the sink that
+    /// the modes were changed through, unless that has already been done.
+    pub(crate) fn restore(&self, sink: &mut impl Write) {
+        restore(&self.modes, sink);
+    }
+}
+
+fn install_panic_hook(modes: Arc<Modes>) {
+    if let Ok(mut latest) = LATEST_MODES.lock() {
+        *latest = Some(modes);
+    }
+    PANIC_HOOK.call_once(|| {
+        let prev_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Don't block if the panic happened while the lock was held
+            if let Ok(latest) = LATEST_MODES.try_lock() {
+                if let Some(modes) = latest.as_ref() {
+                    // The sink can't be reached from here, so write to the
+                    // terminal that it normally targets instead
+                    match open_tty() {
+                        Some(mut tty) => restore(modes, &mut tty),
+                        None => restore(modes, &mut std::io::sink()),
+                    }
+                }
+            }
+            prev_hook(info);
+        }));
+    });
+}
+
+/// Restore the terminal to the state it was in before the `Repl` used it:
+/// turn off raw mode, bracketed paste and mouse capture, reset the cursor
+/// shape and move the cursor below the input area.
+/// The escape sequences are written to `sink`.
+fn restore(modes: &Modes, sink: &mut impl Write) {
+    if !modes.active.swap(false, Ordering::SeqCst) {
+        return; // Already restored
+    }
+    if terminal::is_raw_mode_enabled().unwrap_or(false) {
+        let _ = terminal::disable_raw_mode();
+    }
+    if modes.mouse_capture.swap(false, Ordering::SeqCst) {
+        let _ = queue!(sink, event::DisableMouseCapture);
+    }
+    if modes.bracketed_paste.swap(false, Ordering::SeqCst) {
+        let _ = queue!(sink, event::DisableBracketedPaste);
+    }
+    if modes.cursor_style.swap(false, Ordering::SeqCst) {
+        let _ = queue!(sink, cursor::SetCursorStyle::DefaultUserShape);
+    }
+    // The input area is located at the bottom of the terminal
+    let (_term_width, term_height) = terminal::size().unwrap_or((0, 1));
+    let _ = queue!(
+        sink,
+        cursor::Show,
+        cursor::MoveTo(0, term_height.saturating_sub(1)),
+        crossterm::style::ResetColor,
+    );
+    let _ = writeln!(sink);
+    let _ = sink.flush();
+}
+
+/// Open the controlling terminal, which is what the sink of a `Repl`
+/// normally writes to.
+fn open_tty() -> Option<Box<dyn Write>> {
+    #[cfg(unix)]
+    {
+        let tty = std::fs::OpenOptions::new().write(true).open("/dev/tty").ok()?;
+        Some(Box::new(tty))
+    }
+    #[cfg(not(unix))]
+    {
+        let stdout = std::io::stdout();
+        stdout.is_terminal().then(|| Box::new(stdout) as Box<dyn Write>)
+    }
+}
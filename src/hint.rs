@@ -0,0 +1,98 @@
+//! Inline hints a.k.a. autosuggestions, which are shown dimmed after the
+//! cursor while a cmd is being edited.
+
+use crate::history::History;
+
+/// A `Hinter` suggests how the cmd that is being edited could be completed.
+pub trait Hinter {
+    /// Return the text that would complete `source`, which is the source
+    /// code of the cmd being edited, or `None` if there is no hint.
+    /// The returned text is shown after the cursor, and it's inserted into
+    /// the cmd when the hint is accepted.
+    fn hint(&mut self, source: &str, history: &HistoryView<'_>) -> Option<String>;
+}
+
+impl<F> Hinter for F
+where
+    F: for<'h> FnMut(&str, &HistoryView<'h>) -> Option<String>
+{
+    fn hint(&mut self, source: &str, history: &HistoryView<'_>) -> Option<String> {
+        self(source, history)
+    }
+}
+
+/// A fish-style `Hinter` that suggests the most recent `History` entry that
+/// has the cmd being edited as a prefix.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HistoryHinter;
+
+impl Hinter for HistoryHinter {
+    fn hint(&mut self, source: &str, history: &HistoryView<'_>) -> Option<String> {
+        if source.is_empty() {
+            return None;
+        }
+        history.iter()
+            .find(|entry| entry.len() > source.len() && entry.starts_with(source))
+            .map(|entry| entry[source.len()..].to_string())
+    }
+}
+
+/// A read-only view of the `History` of a `Repl`.
+#[derive(Clone, Copy, Debug)]
+pub struct HistoryView<'h> {
+    history: &'h History,
+}
+
+impl<'h> HistoryView<'h> {
+    pub(crate) fn new(history: &'h History) -> Self {
+        Self { history }
+    }
+
+    /// Return the number of `History` entries.
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.len() == 0
+    }
+
+    /// Iterate over the source code of the `History` entries,
+    /// most recent entry first.
+    pub fn iter(&self) -> impl Iterator<Item = String> + 'h {
+        self.history.iter().rev().map(|(_, cmd)| cmd.to_source_code())
+    }
+}
+
+/// Split off the first word of `hint`, including any leading whitespace.
+/// This is the part of a hint that's accepted with `Alt-F`.
+pub(crate) fn first_word(hint: &str) -> &str {
+    let start = hint.len() - hint.trim_start().len();
+    let end = hint[start..].find(char::is_whitespace)
+        .map(|len| start + len)
+        .unwrap_or(hint.len());
+    &hint[..end]
+}
+
+
+#[cfg(test)]
+mod test {
+    use crate::cmd::Cmd;
+    use super::*;
+
+    #[test]
+    fn history_hints() {
+        let mut history = History::default();
+        for src in ["select * from foo", "select 1", "show tables"] {
+            history.add_cmd(Cmd::from_source_code(src));
+        }
+        let history = HistoryView::new(&history);
+        let mut hinter = HistoryHinter;
+        assert_eq!(hinter.hint("sel", &history), Some("ect 1".to_string()));
+        assert_eq!(hinter.hint("select *", &history), Some(" from foo".to_string()));
+        assert_eq!(hinter.hint("show tables", &history), None);
+        assert_eq!(hinter.hint("", &history), None);
+        assert_eq!(first_word(" from foo"), " from");
+        assert_eq!(first_word("ect 1"), "ect");
+    }
+}
@@ -1,4 +1,4 @@
-//!
+//! The `History` of cmds that were evaluated by a `Repl`.
 
 use camino::Utf8Path;
 use crate::{
@@ -36,18 +36,20 @@ impl History {
                 .read(true)
                 .write(true)
                 .truncate(true)
-                .open(&filepath)?;
+                .open(filepath)?;
             file.write_all(&[])?;
             file.flush()?;
             file
         };
         let mut contents = String::with_capacity(8 * 1024);
         let read_bytes = file.read_to_string(&mut contents)?;
-        if read_bytes == 0 { // emtpy file
-            Ok(Self::default())
+        let history = if read_bytes == 0 { // emtpy file
+            Self::default()
         } else {
-            Ok(serde_json::from_str::<Self>(&contents)?)
-        }
+            serde_json::from_str::<Self>(&contents)?
+        };
+        log::debug!("history: read {} cmds from {filepath}", history.len());
+        Ok(history)
     }
 
     pub fn write_to_file(&self, path: impl AsRef<Utf8Path>) -> ReplBlockResult<()> {
@@ -55,8 +57,10 @@ impl History {
             .truncate(true)
             .write(true)
             .open(path.as_ref())?;
-        let json: String = serde_json::to_string_pretty(&self.trimmed())?;
+        let trimmed = self.trimmed();
+        let json: String = serde_json::to_string_pretty(&trimmed)?;
         file.write_all(json.as_bytes())?;
+        log::debug!("history: wrote {} cmds to {}", trimmed.len(), path.as_ref());
         Ok(())
     }
 
@@ -101,10 +105,19 @@ impl History {
         let Ok(regex) = Regex::new(regex) else { return vec![/*no matches*/] };
         self.iter().rev(/*most recent first*/)
             .map(|(hidx, cmd)| (hidx, cmd, cmd.to_source_code()))
-            .filter(|(_, _, src)| regex.is_match(&src))
+            .filter(|(_, _, src)| regex.is_match(src))
             .map(|(hidx, _, _)| hidx)
             .collect()
     }
+
+    /// Return a `History` containing only the cmds that match `regex`.
+    pub fn filtered(&self, regex: &str) -> Self {
+        let mut cmds = VecDeque::new();
+        for hidx in self.reverse_search(regex) {
+            cmds.push_front(self[hidx].clone());
+        }
+        Self { cmds }
+    }
 }
 
 impl std::fmt::Display for History {
@@ -0,0 +1,114 @@
+//! Automatic indentation of the `Line`s of a cmd.
+
+use crate::bracket;
+
+/// An `Indenter` determines the indentation of the `Line`s of a cmd as it's
+/// being edited.
+pub trait Indenter {
+    /// Return the leading whitespace for a new `Line` that's inserted
+    /// right after `prev_line`, which holds the contents of the line on
+    /// which the newline was inserted, up to the cursor.
+    fn indent(&mut self, prev_line: &str) -> String;
+
+    /// Return the new leading whitespace for a `Line` with the leading
+    /// whitespace `indentation`, when the closing bracket `closer` is typed
+    /// as the first non-whitespace grapheme of that line.
+    /// Return `None` to leave the indentation as it is.
+    fn dedent(&mut self, _indentation: &str, _closer: &str) -> Option<String> {
+        None
+    }
+}
+
+impl<F> Indenter for F
+where
+    F: FnMut(&str) -> String
+{
+    fn indent(&mut self, prev_line: &str) -> String {
+        self(prev_line)
+    }
+}
+
+/// A general purpose `Indenter`.  It copies the leading whitespace
+/// of the previous `Line`, adds one level of indentation after an opening
+/// bracket and removes one level again when a closing bracket is typed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefaultIndenter {
+    unit: String,
+}
+
+impl Default for DefaultIndenter {
+    fn default() -> Self {
+        Self::new("    ")
+    }
+}
+
+impl DefaultIndenter {
+    /// Create a new `DefaultIndenter` that uses `unit` as a single level of
+    /// indentation e.g. 4 spaces or a tab.
+    pub fn new(unit: impl Into<String>) -> Self {
+        Self { unit: unit.into() }
+    }
+}
+
+impl Indenter for DefaultIndenter {
+    fn indent(&mut self, prev_line: &str) -> String {
+        let mut indentation = leading_whitespace(prev_line).to_string();
+        let opens_block = prev_line.trim_end()
+            .chars()
+            .last()
+            .is_some_and(|c| bracket::closer_of(&c.to_string()).is_some());
+        if opens_block {
+            indentation.push_str(&self.unit);
+        }
+        indentation
+    }
+
+    fn dedent(&mut self, indentation: &str, _closer: &str) -> Option<String> {
+        let spaces = indentation.len() - indentation.trim_end_matches(' ').len();
+        let is_space_unit = !self.unit.is_empty() && self.unit.bytes().all(|b| b == b' ');
+        let removed = if is_space_unit && spaces > 0 {
+            // Go back to the previous tab stop, which removes a partial
+            // level of indentation rather than e.g. a single space
+            match spaces % self.unit.len() {
+                0 => self.unit.len(),
+                partial => partial,
+            }
+        } else if !self.unit.is_empty() && indentation.ends_with(self.unit.as_str()) {
+            self.unit.len()
+        } else if spaces > 0 {
+            spaces // E.g. the spaces after a tab, when the unit is a tab
+        } else {
+            indentation.chars().next_back()?.len_utf8()
+        };
+        Some(indentation[..indentation.len() - removed].to_string())
+    }
+}
+
+/// Return the leading whitespace of `line`.
+pub(crate) fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn default_indentation() {
+        let mut indenter = DefaultIndenter::default();
+        assert_eq!(indenter.indent("let x = 1;"), "");
+        assert_eq!(indenter.indent("  let x = 1;"), "  ");
+        assert_eq!(indenter.indent("fn main() {"), "    ");
+        assert_eq!(indenter.indent("    foo(bar, [ "), "        ");
+        assert_eq!(indenter.dedent("        ", "}"), Some("    ".to_string()));
+        assert_eq!(indenter.dedent("  ", "]"), Some("".to_string()));
+        assert_eq!(indenter.dedent("      ", "}"), Some("    ".to_string()));
+        assert_eq!(indenter.dedent("\t", "}"), Some("".to_string()));
+        assert_eq!(indenter.dedent("", ")"), None);
+        let mut tabs = DefaultIndenter::new("\t");
+        assert_eq!(tabs.indent("\tif (x) {"), "\t\t");
+        assert_eq!(tabs.dedent("\t\t", "}"), Some("\t".to_string()));
+        assert_eq!(tabs.dedent("\t  ", "}"), Some("\t".to_string()));
+    }
+}
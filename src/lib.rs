@@ -1,16 +1,51 @@
-//!
+//! A crossterm-based library for building Read-Eval-Print-Loops (REPLs).
 
+mod asciicast;
+mod binding;
+mod bracket;
+mod capability;
+mod capture;
+mod clipboard;
 mod cmd;
+mod editor;
 mod repl;
 mod error;
+mod expansion;
+mod guard;
 mod history;
+mod hint;
+mod indent;
 mod macros;
+mod meta;
+mod prompt;
+mod screen;
+mod suspend;
+mod theme;
+mod transcript;
+mod tty;
+
+/// Internals that are only exposed for the benchmarks in `benches/`.
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::screen::{Frame, Screen};
+}
 
 pub mod prelude {
     pub use camino::{Utf8Path, Utf8PathBuf};
     pub use crate::{
-        repl::{Repl, ReplBuilder},
+        asciicast::{Asciicast, AsciicastSink},
+        capability::{ColorSupport, TermCapabilities},
+        repl::{Coords, Repl, ReplBuilder},
         error::{ReplBlockError, ReplBlockResult},
+        clipboard::{Clipboard, InMemoryClipboard, Osc52Clipboard},
+        cmd::{Cmd, Line, Tabs},
+        hint::{Hinter, HistoryHinter, HistoryView},
+        indent::{DefaultIndenter, Indenter},
+        prompt::{EvalAction, Prompt, PromptContext, PromptSeed, StaticPrompt, Status, StyledText},
+        theme::{Style, Theme},
+        transcript::{Mismatch, Transcript, TranscriptEntry},
     };
+    pub use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
     pub use crossterm::style::{Color, Stylize};
 }
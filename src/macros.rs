@@ -1,4 +1,4 @@
-//!
+//! Macros for matching on crossterm `KeyEvent`s.
 
 macro_rules! key {
     (@name $($modifier:ident)|+ - $name:ident) => {
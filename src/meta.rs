@@ -0,0 +1,105 @@
+//! Meta-commands, i.e. housekeeping commands such as `:help` that are
+//! intercepted and handled by the `Repl` before reaching the evaluator.
+
+use crate::error::ReplBlockResult;
+
+pub(crate) type MetaHandler<'eval> =
+    dyn for<'args> FnMut(&'args str) -> ReplBlockResult<()> + 'eval;
+
+/// A meta-command registered by the host application.
+pub(crate) struct MetaCmd<'eval> {
+    pub(crate) name: String,
+    pub(crate) help: String,
+    pub(crate) handler: Box<MetaHandler<'eval>>,
+}
+
+impl<'eval> std::fmt::Debug for MetaCmd<'eval> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaCmd")
+            .field("name", &self.name)
+            .field("help", &self.help)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The meta-commands that are built into the `Repl`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Builtin {
+    Help,
+    History,
+    Clear,
+    Save,
+    Keys,
+}
+
+impl Builtin {
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Help,
+        Self::History,
+        Self::Clear,
+        Self::Save,
+        Self::Keys,
+    ];
+
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Help    => "help",
+            Self::History => "history",
+            Self::Clear   => "clear",
+            Self::Save    => "save",
+            Self::Keys    => "keys",
+        }
+    }
+
+    pub(crate) fn usage(&self) -> &'static str {
+        match self {
+            Self::Help    => "",
+            Self::History => "[REGEX]",
+            Self::Clear   => "",
+            Self::Save    => "FILE",
+            Self::Keys    => "",
+        }
+    }
+
+    pub(crate) fn help(&self) -> &'static str {
+        match self {
+            Self::Help    => "Show this help message",
+            Self::History => "List the history, or the entries matching REGEX",
+            Self::Clear   => "Clear the screen",
+            Self::Save    => "Save the cmds of this session to FILE",
+            Self::Keys    => "Show the key bindings",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|builtin| builtin.name() == name)
+    }
+}
+
+/// If `source` starts with `prefix`, split it into the name of a
+/// meta-command and its (trimmed) arguments.
+pub(crate) fn parse<'src>(prefix: &str, source: &'src str) -> Option<(&'src str, &'src str)> {
+    let rest = source.trim().strip_prefix(prefix)?;
+    let (name, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_meta_cmds() {
+        assert_eq!(parse(":", ":help"), Some(("help", "")));
+        assert_eq!(parse(":", "  :history  foo.*bar "), Some(("history", "foo.*bar")));
+        assert_eq!(parse("\\", "\\save out.txt"), Some(("save", "out.txt")));
+        assert_eq!(parse(":", ":"), None);
+        assert_eq!(parse(":", "help"), None);
+        assert_eq!(Builtin::from_name("keys"), Some(Builtin::Keys));
+        assert_eq!(Builtin::from_name("quit"), None);
+    }
+}
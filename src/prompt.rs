@@ -0,0 +1,254 @@
+//! Prompts, which are rendered fresh before each cmd.
+
+use crate::capability::TermCapabilities;
+use crate::screen;
+use crossterm::style::{ContentStyle, StyledContent};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A `Prompt` renders the prompts that are shown in front of the `Line`s of
+/// a cmd.  It is asked to render them anew before each cmd, which means
+/// that a prompt can reflect the state of the REPL or the host application.
+pub trait Prompt {
+    /// Render the prompt shown on the first line of a cmd.
+    fn default_prompt(&mut self, ctx: &PromptContext) -> StyledText;
+
+    /// Render the prompt shown on each continuation line of a cmd.
+    /// It doesn't need to have the same width as the default prompt.
+    fn continue_prompt(&mut self, ctx: &PromptContext) -> StyledText;
+
+    /// Render a prompt that is shown at the right edge of the first line of a
+    /// cmd, like zsh's `RPROMPT`.  It is hidden automatically whenever the
+    /// first line of the cmd gets long enough to collide with it.
+    fn right_prompt(&mut self, _ctx: &PromptContext) -> Option<StyledText> {
+        None
+    }
+
+    /// Render a status line that is shown underneath the input area.
+    fn status_line(&mut self, _ctx: &PromptContext) -> Option<StyledText> {
+        None
+    }
+}
+
+/// Information about the `Repl` that is available when rendering a `Prompt`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PromptContext {
+    /// The number of cmds that were evaluated during this session
+    pub cmd_count: usize,
+    /// The status of the last evaluated cmd,
+    /// or `None` if no cmd has been evaluated yet during this session.
+    pub last_status: Option<Status>,
+    /// How long it took to evaluate the last evaluated cmd,
+    /// or `None` if no cmd has been evaluated yet during this session.
+    pub last_duration: Option<Duration>,
+}
+
+/// The outcome of evaluating a cmd.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+    Success,
+    Failure,
+}
+
+/// What the `Repl` should do after the evaluator evaluated a cmd, before
+/// it shows the next prompt.  An evaluator that returns `()` returns
+/// `EvalAction::None`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum EvalAction {
+    /// Show the next prompt as usual
+    #[default]
+    None,
+    /// Edit `text` at the next prompt, e.g. to let the user correct a cmd
+    /// that was rejected by the evaluator.  The cursor is placed at its end,
+    /// and the cmd is recorded as having failed.
+    Edit(String),
+    /// Insert `text` at the cursor at the next prompt, after any seed (see
+    /// `PromptSeed`) was applied
+    Insert(String),
+    /// Clear the screen, including the output of the evaluator
+    ClearScreen,
+}
+
+impl From<()> for EvalAction {
+    fn from((): ()) -> Self {
+        Self::None
+    }
+}
+
+/// A handle for seeding the cmd that's edited at the next prompt with text,
+/// e.g. from within the evaluator to re-edit a cmd that failed.
+/// Clones share the seed, so that a clone can be handed to the `Repl` while
+/// another one is used to set the seed.
+/// A seed that's set before the `Repl` is built pre-fills the first prompt.
+#[derive(Clone, Debug, Default)]
+pub struct PromptSeed {
+    text: Rc<RefCell<Option<String>>>,
+}
+
+impl PromptSeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the next prompt with `text`.  The cursor is placed at its end.
+    pub fn set(&self, text: impl Into<String>) {
+        *self.text.borrow_mut() = Some(text.into());
+    }
+
+    /// Take the seed, if any, leaving `None` in its place.
+    pub fn take(&self) -> Option<String> {
+        self.text.borrow_mut().take()
+    }
+}
+
+/// A `Prompt` that renders the same prompts every time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StaticPrompt {
+    default: StyledText,
+    continued: StyledText,
+}
+
+impl StaticPrompt {
+    pub fn new(default: impl Into<StyledText>, continued: impl Into<StyledText>) -> Self {
+        Self {
+            default: default.into(),
+            continued: continued.into(),
+        }
+    }
+}
+
+impl Prompt for StaticPrompt {
+    fn default_prompt(&mut self, _ctx: &PromptContext) -> StyledText {
+        self.default.clone()
+    }
+
+    fn continue_prompt(&mut self, _ctx: &PromptContext) -> StyledText {
+        self.continued.clone()
+    }
+}
+
+
+/// A sequence of styled strings e.g. a prompt.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StyledText {
+    parts: Vec<StyledContent<String>>,
+}
+
+impl StyledText {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a styled part to `self`.
+    pub fn push(&mut self, part: impl Into<StyledText>) {
+        self.parts.extend(part.into().parts);
+    }
+
+    /// Append a styled part to `self`, builder style.
+    pub fn with(mut self, part: impl Into<StyledText>) -> Self {
+        self.push(part);
+        self
+    }
+
+    /// Return the width of `self`, in columns.
+    pub fn width(&self) -> u16 {
+        self.parts.iter()
+            .flat_map(|part| part.content().graphemes(true))
+            .map(screen::grapheme_width)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.iter().all(|part| part.content().is_empty())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &StyledContent<String>> {
+        self.parts.iter()
+    }
+
+    /// Downgrade the styles of `self` to what a terminal with the
+    /// capabilities `caps` is able to display.
+    pub fn downgrade(self, caps: &TermCapabilities) -> Self {
+        Self {
+            parts: self.parts.into_iter()
+                .map(|part| {
+                    let style = caps.downgrade_style(*part.style());
+                    StyledContent::new(style, part.content().clone())
+                })
+                .collect()
+        }
+    }
+
+    /// Return the unstyled contents of `self`.
+    pub fn to_plain_string(&self) -> String {
+        self.parts.iter().map(|part| part.content().as_str()).collect()
+    }
+}
+
+impl From<Vec<StyledContent<String>>> for StyledText {
+    fn from(parts: Vec<StyledContent<String>>) -> Self {
+        Self { parts }
+    }
+}
+
+impl From<Vec<StyledContent<char>>> for StyledText {
+    fn from(chars: Vec<StyledContent<char>>) -> Self {
+        Self {
+            parts: chars.into_iter()
+                .map(|c| StyledContent::new(*c.style(), c.content().to_string()))
+                .collect()
+        }
+    }
+}
+
+impl From<Vec<StyledContent<&str>>> for StyledText {
+    fn from(parts: Vec<StyledContent<&str>>) -> Self {
+        Self {
+            parts: parts.into_iter()
+                .map(|part| StyledContent::new(*part.style(), part.content().to_string()))
+                .collect()
+        }
+    }
+}
+
+impl From<StyledContent<String>> for StyledText {
+    fn from(part: StyledContent<String>) -> Self {
+        Self { parts: vec![part] }
+    }
+}
+
+impl From<StyledContent<&str>> for StyledText {
+    fn from(part: StyledContent<&str>) -> Self {
+        Self::from(StyledContent::new(*part.style(), part.content().to_string()))
+    }
+}
+
+impl From<StyledContent<char>> for StyledText {
+    fn from(c: StyledContent<char>) -> Self {
+        Self::from(StyledContent::new(*c.style(), c.content().to_string()))
+    }
+}
+
+impl From<String> for StyledText {
+    fn from(s: String) -> Self {
+        Self::from(StyledContent::new(ContentStyle::new(), s))
+    }
+}
+
+impl From<&str> for StyledText {
+    fn from(s: &str) -> Self {
+        Self::from(s.to_string())
+    }
+}
+
+impl std::fmt::Display for StyledText {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for part in &self.parts {
+            write!(f, "{part}")?;
+        }
+        Ok(())
+    }
+}
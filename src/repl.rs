@@ -1,68 +1,111 @@
-//!
+//! The `Repl` itself, as well as the `ReplBuilder` used to configure it.
 
 use crate::{
-    cmd::{Cmd, Line},
+    asciicast::{Asciicast, AsciicastSink},
+    binding::{self, KeyBinding},
+    bracket,
+    capture::{StdoutCapture, Tee},
+    capability::TermCapabilities,
+    clipboard::{Clipboard, Osc52Clipboard},
+    cmd::{self, Cmd, Line, Tabs},
+    editor,
     error::ReplBlockResult,
+    expansion,
+    guard::TerminalGuard,
+    hint::{self, Hinter, HistoryView},
+    indent::{self, Indenter},
     history::{History, HistIdx},
     macros::key,
+    meta::{self, Builtin, MetaCmd},
+    prompt::{EvalAction, Prompt, PromptContext, PromptSeed, StaticPrompt, Status, StyledText},
+    screen::{Frame, Screen},
+    suspend::{self, Signals, Wakeup},
+    theme::{Style as ThemeStyle, Theme},
+    transcript::Transcript,
+    tty::EvalMode,
 };
 use camino::{Utf8Path, Utf8PathBuf};
+use regex::Regex;
 use crossterm::{
     cursor, execute, queue, style, terminal,
     event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    style::{Stylize, StyledContent},
+    event::{MouseButton, MouseEvent, MouseEventKind},
+    style::ContentStyle,
     terminal::ClearType,
 };
+use std::collections::VecDeque;
 use std::io::{Stdout, Write};
+use std::time::{Duration, Instant, SystemTime};
 use unicode_segmentation::UnicodeSegmentation;
 
 
 type Evaluator<'eval> =
-    dyn for<'src> FnMut(&'src str) -> ReplBlockResult<()> + 'eval;
+    dyn for<'src> FnMut(&'src str) -> ReplBlockResult<EvalAction> + 'eval;
 
 pub struct ReplBuilder<'eval, W: Write> {
     sink: W,
-    default_prompt: Vec<StyledContent<char>>,
-    continue_prompt: Vec<StyledContent<char>>,
-    reverse_search_prompt: Vec<StyledContent<char>>,
+    default_prompt: Option<StyledText>,
+    continue_prompt: Option<StyledText>,
+    prompt: Option<Box<dyn Prompt + 'eval>>,
+    reverse_search_prompt: Option<StyledText>,
     history_filepath: Utf8PathBuf,
+    transcript_filepath: Option<Utf8PathBuf>,
+    asciicast: Option<Asciicast>,
     evaluator: Box<Evaluator<'eval>>,
-    hello_msg: String,
+    hello_msg: Option<String>,
     goodbye_msg: String,
+    history_expansion: bool,
+    meta_commands: bool,
+    meta_prefix: String,
+    meta_cmds: Vec<MetaCmd<'eval>>,
+    key_bindings: Vec<KeyBinding<'eval>>,
+    prompt_seed: PromptSeed,
+    eval_after_external_edit: bool,
+    quiet_eval: bool,
+    auto_pair: bool,
+    tabs: Tabs,
+    mouse_capture: bool,
+    hinter: Option<Box<dyn Hinter + 'eval>>,
+    indenter: Option<Box<dyn Indenter + 'eval>>,
+    clipboard: Box<dyn Clipboard + 'eval>,
+    theme: Theme,
+    term_capabilities: TermCapabilities,
 }
 
 impl<'eval> Default for ReplBuilder<'eval, Stdout> {
     fn default() -> ReplBuilder<'eval, Stdout> {
         #[inline(always)]
         fn nop<'eval>() -> Box<Evaluator<'eval>> {
-            Box::new(|_| Ok(()))
+            Box::new(|_| Ok(EvalAction::None))
         }
         ReplBuilder {
             sink: std::io::stdout(),
-            default_prompt:  vec!['■'.yellow(), '>'.green().bold(), ' '.reset()],
-            continue_prompt: vec!['.'.yellow(), '.'.yellow(),       ' '.reset()],
-            reverse_search_prompt: vec![
-                'r'.yellow().italic(),
-                'e'.yellow().italic(),
-                'v'.yellow().italic(),
-                'e'.yellow().italic(),
-                'r'.yellow().italic(),
-                's'.yellow().italic(),
-                'e'.yellow().italic(),
-                ' '.reset(),
-                's'.yellow().italic(),
-                'e'.yellow().italic(),
-                'a'.yellow().italic(),
-                'r'.yellow().italic(),
-                'c'.yellow().italic(),
-                'h'.yellow().italic(),
-                ':'.blue().italic(),
-                ' '.reset(),
-            ],
+            default_prompt: None,
+            continue_prompt: None,
+            prompt: None,
+            reverse_search_prompt: None,
             history_filepath: Utf8PathBuf::from(".repl.history"),
+            transcript_filepath: None,
+            asciicast: None,
             evaluator: nop(),
-            hello_msg: format!("🖐 Press {} to exit.",  "Ctrl-D".magenta()),
+            hello_msg: None,
             goodbye_msg: "👋".to_string(),
+            history_expansion: false,
+            meta_commands: false,
+            meta_prefix: ":".to_string(),
+            meta_cmds: vec![],
+            key_bindings: vec![],
+            prompt_seed: PromptSeed::new(),
+            eval_after_external_edit: false,
+            quiet_eval: false,
+            auto_pair: false,
+            tabs: Tabs::default(),
+            mouse_capture: false,
+            hinter: None,
+            indenter: None,
+            clipboard: Box::new(Osc52Clipboard::new()),
+            theme: Theme::from_env(),
+            term_capabilities: TermCapabilities::detect(),
         }
     }
 }
@@ -73,26 +116,55 @@ impl<'eval, W: Write> ReplBuilder<'eval, W> {
             sink,
             default_prompt: self.default_prompt,
             continue_prompt: self.continue_prompt,
+            prompt: self.prompt,
             reverse_search_prompt: self.reverse_search_prompt,
             history_filepath: self.history_filepath,
+            transcript_filepath: self.transcript_filepath,
+            asciicast: self.asciicast,
             evaluator: self.evaluator,
             hello_msg: self.hello_msg,
             goodbye_msg: self.goodbye_msg,
+            history_expansion: self.history_expansion,
+            meta_commands: self.meta_commands,
+            meta_prefix: self.meta_prefix,
+            meta_cmds: self.meta_cmds,
+            key_bindings: self.key_bindings,
+            prompt_seed: self.prompt_seed,
+            eval_after_external_edit: self.eval_after_external_edit,
+            quiet_eval: self.quiet_eval,
+            auto_pair: self.auto_pair,
+            tabs: self.tabs,
+            mouse_capture: self.mouse_capture,
+            hinter: self.hinter,
+            indenter: self.indenter,
+            clipboard: self.clipboard,
+            theme: self.theme,
+            term_capabilities: self.term_capabilities,
         }
     }
 
-    pub fn default_prompt(mut self, prompt: Vec<StyledContent<char>>) -> Self {
-        self.default_prompt = prompt;
+    /// Set a fixed default prompt.
+    /// This is ignored if a dynamic prompt is set with `ReplBuilder::prompt()`.
+    pub fn default_prompt(mut self, prompt: impl Into<StyledText>) -> Self {
+        self.default_prompt = Some(prompt.into());
+        self
+    }
+
+    /// Set a fixed continuation prompt.
+    /// This is ignored if a dynamic prompt is set with `ReplBuilder::prompt()`.
+    pub fn continue_prompt(mut self, prompt: impl Into<StyledText>) -> Self {
+        self.continue_prompt = Some(prompt.into());
         self
     }
 
-    pub fn continue_prompt(mut self, prompt: Vec<StyledContent<char>>) -> Self {
-        self.continue_prompt = prompt;
+    /// Set a dynamic `Prompt`, which is rendered anew before each cmd.
+    pub fn prompt(mut self, prompt: impl Prompt + 'eval) -> Self {
+        self.prompt = Some(Box::new(prompt));
         self
     }
 
-    pub fn reverse_search_prompt(mut self, prompt: Vec<StyledContent<char>>) -> Self {
-        self.reverse_search_prompt = prompt;
+    pub fn reverse_search_prompt(mut self, prompt: impl Into<StyledText>) -> Self {
+        self.reverse_search_prompt = Some(prompt.into());
         self
     }
 
@@ -101,16 +173,36 @@ impl<'eval, W: Write> ReplBuilder<'eval, W> {
         self
     }
 
-    pub fn evaluator<E>(mut self, evaluator: E) -> Self
+    /// Record a `Transcript` of the session to the file at `filepath`, which
+    /// is overwritten.  The transcript is updated after each evaluated cmd.
+    /// Disabled by default.
+    pub fn transcript_filepath(mut self, filepath: impl AsRef<Utf8Path>) -> Self {
+        self.transcript_filepath = Some(filepath.as_ref().to_path_buf());
+        self
+    }
+
+    /// Record the session in `asciicast`: everything that's written to the
+    /// sink or by the evaluator, as well as the input and resize events.
+    pub fn asciicast(mut self, asciicast: Asciicast) -> Self {
+        self.asciicast = Some(asciicast);
+        self
+    }
+
+    /// Set the evaluator, which evaluates each cmd that isn't a
+    /// meta-command.  Besides `()`, it can return an `EvalAction`, to have the
+    /// `Repl` e.g. hand a rejected cmd back to the user for correction.
+    /// An error is printed, and the cmd is recorded as having failed.
+    pub fn evaluator<E, A>(mut self, mut evaluator: E) -> Self
     where
-        E: for<'src> FnMut(&'src str) -> ReplBlockResult<()> + 'eval
+        E: for<'src> FnMut(&'src str) -> ReplBlockResult<A> + 'eval,
+        A: Into<EvalAction>,
     {
-        self.evaluator = Box::new(evaluator);
+        self.evaluator = Box::new(move |src| evaluator(src).map(Into::into));
         self
     }
 
     pub fn hello(mut self, hello_msg: impl Into<String>) -> Self {
-        self.hello_msg = hello_msg.into();
+        self.hello_msg = Some(hello_msg.into());
         self
     }
 
@@ -119,23 +211,167 @@ impl<'eval, W: Write> ReplBuilder<'eval, W> {
         self
     }
 
+    /// Enable or disable shell-style history expansion, i.e. the rewriting of
+    /// `!!`, `!-n`, `!n`, `!prefix` and `^old^new` against the `History`
+    /// before a cmd is evaluated.  Disabled by default.
+    pub fn history_expansion(mut self, enabled: bool) -> Self {
+        self.history_expansion = enabled;
+        self
+    }
+
+    /// Enable or disable meta-commands, i.e. cmds like `:help` that are
+    /// handled by the REPL itself rather than by the evaluator.
+    /// Disabled by default, so that all input reaches the evaluator.
+    pub fn meta_commands(mut self, enabled: bool) -> Self {
+        self.meta_commands = enabled;
+        self
+    }
+
+    /// Set the prefix that distinguishes meta-commands from regular cmds.
+    /// Defaults to `:`.
+    pub fn meta_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.meta_prefix = prefix.into();
+        self
+    }
+
+    /// Register a meta-command with a `name` and a `help` text.
+    /// The `handler` is called with the (trimmed) arguments of the
+    /// meta-command.  Host meta-commands take precedence over the built-in
+    /// ones, so they can be used to override e.g. `help`.
+    pub fn meta_command<H>(
+        mut self,
+        name: impl Into<String>,
+        help: impl Into<String>,
+        handler: H,
+    ) -> Self
+    where
+        H: for<'args> FnMut(&'args str) -> ReplBlockResult<()> + 'eval
+    {
+        self.meta_cmds.push(MetaCmd {
+            name: name.into(),
+            help: help.into(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Bind `key` to a custom `action`, which is described by `description`
+    /// in the help for the key bindings.  The action is called with the cmd
+    /// being edited and the cursor, both of which it may modify.
+    /// Only the key code and modifiers of `key` are matched against key
+    /// presses.  Custom key bindings take precedence over the built-in ones.
+    pub fn key_binding<A>(
+        mut self,
+        key: KeyEvent,
+        description: impl Into<String>,
+        action: A,
+    ) -> Self
+    where
+        A: FnMut(&mut Cmd, &mut Coords) -> ReplBlockResult<()> + 'eval
+    {
+        self.key_bindings.push(KeyBinding {
+            key,
+            description: description.into(),
+            action: Box::new(action),
+        });
+        self
+    }
+
+    /// Set the `PromptSeed` through which the cmd at the next prompt can be
+    /// seeded with text, e.g. by the evaluator.
+    pub fn prompt_seed(mut self, seed: PromptSeed) -> Self {
+        self.prompt_seed = seed;
+        self
+    }
+
+    /// Whether or not to immediately evaluate a cmd after it has been edited
+    /// in an external editor (see `Ctrl-X Ctrl-E`).  Disabled by default.
+    pub fn eval_after_external_edit(mut self, enabled: bool) -> Self {
+        self.eval_after_external_edit = enabled;
+        self
+    }
+
+    /// Whether or not to keep keys that are typed while a cmd is evaluated
+    /// from being echoed over its output.  They're replayed into the next
+    /// prompt either way.  This turns off echoing and line editing of stdin
+    /// during evaluation, so leave it disabled for evaluators that read from
+    /// stdin, e.g. to prompt the user.  Disabled by default.
+    pub fn quiet_eval(mut self, enabled: bool) -> Self {
+        self.quiet_eval = enabled;
+        self
+    }
+
+    /// Whether or not to automatically insert the closing bracket or quote
+    /// when an opening one is typed.  Typing the closer right in front of
+    /// it then moves the cursor over it.  Disabled by default.
+    pub fn auto_pair(mut self, enabled: bool) -> Self {
+        self.auto_pair = enabled;
+        self
+    }
+
+    /// Set how tab characters are handled i.e. whether they're expanded to
+    /// spaces when they're inserted, or kept and rendered up to the next
+    /// tab stop.  Defaults to `Tabs::Render(8)`.
+    pub fn tabs(mut self, tabs: Tabs) -> Self {
+        self.tabs = tabs;
+        self
+    }
+
+    /// Whether or not to capture the mouse.  When enabled, clicking in the
+    /// input area moves the cursor, dragging selects text and the scroll
+    /// wheel navigates the history.  Note that this prevents the terminal
+    /// from handling the mouse itself, e.g. for selecting text.
+    /// Disabled by default.
+    pub fn mouse_capture(mut self, enabled: bool) -> Self {
+        self.mouse_capture = enabled;
+        self
+    }
+
+    /// Register a `Hinter` that provides inline hints while a cmd is being
+    /// edited, e.g. `HistoryHinter` for fish-style autosuggestions.
+    /// A hint is accepted with `Right` or `End`, or word by word with `Alt-F`.
+    pub fn hinter(mut self, hinter: impl Hinter + 'eval) -> Self {
+        self.hinter = Some(Box::new(hinter));
+        self
+    }
+
+    /// Set the `Indenter` that determines the indentation of new `Line`s,
+    /// as well as the dedentation when a closing bracket is typed.
+    /// Disabled by default.  Use e.g. `DefaultIndenter::default()` to indent
+    /// by 4 spaces.
+    pub fn indenter(mut self, indenter: impl Indenter + 'eval) -> Self {
+        self.indenter = Some(Box::new(indenter));
+        self
+    }
+
+    /// Set the `Clipboard` that cut or copied text is stored on.
+    /// Defaults to an `Osc52Clipboard`, which copies text to the system
+    /// clipboard of the terminal.
+    pub fn clipboard(mut self, clipboard: impl Clipboard + 'eval) -> Self {
+        self.clipboard = Box::new(clipboard);
+        self
+    }
+
+    /// Set the `Theme` used to style the prompts, hints, search results etc.
+    /// Defaults to `Theme::from_env()`.
+    /// Prompts that are set explicitly are not affected by the theme.
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Override the detected capabilities of the terminal.
+    /// Colors are downgraded to what the terminal is able to display, and on
+    /// dumb terminals no styling, cursor shapes or scroll sequences are used.
+    /// Defaults to `TermCapabilities::detect()`.
+    pub fn term_capabilities(mut self, term_capabilities: TermCapabilities) -> Self {
+        self.term_capabilities = term_capabilities;
+        self
+    }
+
     pub fn build(self) -> ReplBlockResult<Repl<'eval, W>> {
-        assert_eq!(
-            self.default_prompt.len(), self.continue_prompt.len(),
-            "default_prompt.len() != continue_prompt.len()"
-        );
-        let mut repl = Repl::new(
-            self.sink,
-            self.history_filepath,
-            self.evaluator,
-            self.default_prompt,
-            self.continue_prompt,
-            self.reverse_search_prompt,
-            self.hello_msg,
-            self.goodbye_msg,
-        )?;
-        repl.render_default_prompt()?;
-        repl.sink.flush()?;
+        let mut repl = Repl::new(self)?;
+        repl.render_ui(repl.height)?;
         Ok(repl)
     }
 }
@@ -143,57 +379,201 @@ impl<'eval, W: Write> ReplBuilder<'eval, W> {
 
 
 pub struct Repl<'eval, W: Write> {
-    sink: W,
+    /// The sink, which also records to the asciicast, if any
+    sink: AsciicastSink<W>,
     state: State,
     /// The height of the input area, in lines
     height: u16,
+    /// The index of the first uncompressed line that is visible in the input
+    /// area, for cmds that don't fit on the terminal
+    scroll: u16,
     /// The history of cmds
     history: History,
     /// The filepath of the history file
     history_filepath: Utf8PathBuf,
+    /// The transcript of this session
+    transcript: Transcript,
+    /// The filepath of the transcript file, if the session is recorded
+    transcript_filepath: Option<Utf8PathBuf>,
+    /// The asciicast recording of this session, if any
+    asciicast: Option<Asciicast>,
     /// The fn used to perform the Evaluate step of the REPL
     evaluator: Box<Evaluator<'eval>>,
-    /// The default command prompt
-    default_prompt: Vec<StyledContent<char>>,
-    /// The command prompt used for command continuations
-    continue_prompt: Vec<StyledContent<char>>,
+    /// Renders the default and continuation prompts before each cmd
+    prompt: Box<dyn Prompt + 'eval>,
+    /// The default command prompt, as rendered for the current cmd
+    default_prompt: StyledText,
+    /// The command prompt used for command continuations,
+    /// as rendered for the current cmd
+    continue_prompt: StyledText,
+    /// The prompt shown at the right edge of the first line, if any
+    right_prompt: Option<StyledText>,
+    /// The status line shown underneath the input area, if any
+    status_line: Option<StyledText>,
     /// The prompt used for reverse history search
-    reverse_search_prompt: Vec<StyledContent<char>>,
+    reverse_search_prompt: StyledText,
+    /// The styles used for rendering
+    theme: Theme,
+    /// What the terminal is capable of
+    term_capabilities: TermCapabilities,
     hello_msg: String,
     goodbye_msg: String,
+    /// Whether or not to perform history expansion before evaluation
+    history_expansion: bool,
+    /// Whether or not meta-commands are intercepted before evaluation
+    meta_commands: bool,
+    /// The prefix that distinguishes meta-commands from regular cmds
+    meta_prefix: String,
+    /// The meta-commands registered by the host application
+    meta_cmds: Vec<MetaCmd<'eval>>,
+    /// The key bindings registered by the host application
+    key_bindings: Vec<KeyBinding<'eval>>,
+    /// Text to seed the next prompt with
+    prompt_seed: PromptSeed,
+    /// The number of `History` entries that predate this session
+    session_start: usize,
+    /// Whether or not to evaluate a cmd right after editing it externally
+    eval_after_external_edit: bool,
+    /// Whether or not to keep keys typed during evaluation from being echoed
+    quiet_eval: bool,
+    auto_pair: bool,
+    tabs: Tabs,
+    mouse_capture: bool,
+    /// Whether or not `Ctrl-X` was pressed as the first key of a key chord
+    ctrl_x_pending: bool,
+    /// Whether or not the debug overlay is shown
+    debug_overlay: bool,
+    /// Where the left mouse button was last pressed, in cmd coordinates
+    mouse_anchor: Option<Coords>,
+    /// Flags that are set when job control signals are received
+    signals: Signals,
+    /// Restores the terminal if the `Repl` panics, fails or is dropped
+    guard: TerminalGuard,
+    /// What's currently displayed in the input area
+    screen: Screen,
+    /// Events for keys that were typed while a cmd was being evaluated,
+    /// which are replayed into the next prompt
+    typeahead: VecDeque<Event>,
+    /// The mark, which together with the cursor delimits the selected region
+    mark: Option<Coords>,
+    /// Whether or not the region was selected with `Shift` + movement keys
+    shift_selecting: bool,
+    /// The clipboard that cut or copied text is stored on
+    clipboard: Box<dyn Clipboard + 'eval>,
+    /// Provides the inline hints
+    hinter: Option<Box<dyn Hinter + 'eval>>,
+    indenter: Option<Box<dyn Indenter + 'eval>>,
+    /// The hint that is currently being shown, if any
+    hint: Option<String>,
+    /// The number of cmds evaluated during this session
+    cmd_count: usize,
+    /// The status of the last evaluated cmd, if any
+    last_status: Option<Status>,
+    /// How long it took to evaluate the last evaluated cmd, if any
+    last_duration: Option<Duration>,
 }
 
 impl<'eval, W: Write> Repl<'eval, W> {
-    fn new(
-        mut sink: W,
-        history_filepath: impl AsRef<Utf8Path>,
-        evaluator: Box<Evaluator<'eval>>,
-        default_prompt: Vec<StyledContent<char>>,
-        continue_prompt: Vec<StyledContent<char>>,
-        reverse_search_prompt: Vec<StyledContent<char>>,
-        hello_msg: String,
-        goodbye_msg: String,
-    ) -> ReplBlockResult<Repl<'eval, W>> {
+    fn new(builder: ReplBuilder<'eval, W>) -> ReplBlockResult<Repl<'eval, W>> {
+        let ReplBuilder {
+            mut sink,
+            default_prompt,
+            continue_prompt,
+            prompt,
+            reverse_search_prompt,
+            history_filepath,
+            transcript_filepath,
+            asciicast,
+            evaluator,
+            hello_msg,
+            goodbye_msg,
+            history_expansion,
+            meta_commands,
+            meta_prefix,
+            meta_cmds,
+            key_bindings,
+            prompt_seed,
+            eval_after_external_edit,
+            quiet_eval,
+            auto_pair,
+            tabs,
+            mouse_capture,
+            hinter,
+            indenter,
+            clipboard,
+            theme,
+            term_capabilities,
+        } = builder;
+        let theme = theme.downgrade(&term_capabilities);
         sink.flush()?;
+        let history = History::read_from_file(&history_filepath)?;
         let mut repl = Self {
-            sink,
+            sink: AsciicastSink::wrap(sink, asciicast.clone()),
             state: State::Edit(EditState {
                 buffer: Cmd::default(),
                 cursor: ORIGIN,
             }),
             height: 1,
-            history: History::read_from_file(history_filepath.as_ref())?,
-            history_filepath: history_filepath.as_ref().to_path_buf(),
+            scroll: 0,
+            session_start: history.len(),
+            history,
+            history_filepath,
+            transcript: Transcript::new(),
+            transcript_filepath,
+            asciicast,
             evaluator,
-            default_prompt,
-            continue_prompt,
-            reverse_search_prompt,
-            hello_msg,
+            prompt: prompt.unwrap_or_else(|| {
+                Box::new(StaticPrompt::new(
+                    default_prompt.unwrap_or_else(|| theme.default_prompt_text()),
+                    continue_prompt.unwrap_or_else(|| theme.continue_prompt_text()),
+                ))
+            }),
+            default_prompt: StyledText::new(),
+            continue_prompt: StyledText::new(),
+            right_prompt: None,
+            status_line: None,
+            reverse_search_prompt: reverse_search_prompt
+                .unwrap_or_else(|| theme.search_prompt_text())
+                .downgrade(&term_capabilities),
+            theme,
+            term_capabilities,
+            hello_msg: hello_msg.unwrap_or_else(|| {
+                format!("🖐 Press {} to exit.", theme.accent.apply("Ctrl-D"))
+            }),
             goodbye_msg,
+            history_expansion,
+            meta_commands,
+            meta_prefix,
+            meta_cmds,
+            key_bindings,
+            prompt_seed,
+            eval_after_external_edit,
+            quiet_eval,
+            auto_pair,
+            tabs,
+            mouse_capture,
+            ctrl_x_pending: false,
+            debug_overlay: false,
+            mouse_anchor: None,
+            signals: Signals::register()?,
+            guard: TerminalGuard::new(),
+            screen: Screen::default(),
+            typeahead: VecDeque::new(),
+            mark: None,
+            shift_selecting: false,
+            clipboard,
+            hinter,
+            indenter,
+            hint: None,
+            cmd_count: 0,
+            last_status: None,
+            last_duration: None,
         };
+        repl.refresh_prompts();
+        repl.apply_prompt_seed();
+        repl.height = repl.min_height();
         execute!(
             repl.sink,
-            cursor::SetCursorStyle::BlinkingBar,
             cursor::MoveToColumn(0),
             style::Print(&repl.hello_msg),
             style::Print("\n"),
@@ -202,131 +582,372 @@ impl<'eval, W: Write> Repl<'eval, W> {
     }
 }
 
+impl<'eval, W: Write> Drop for Repl<'eval, W> {
+    /// Restore the terminal, so that the user isn't left with a broken one.
+    fn drop(&mut self) {
+        self.guard.restore(&mut self.sink);
+    }
+}
+
 impl<'eval, W: Write> Repl<'eval, W> {
     pub fn start(&mut self) -> ReplBlockResult<()> {
+        self.guard.rearm();
+        self.set_cursor_style(cursor::SetCursorStyle::BlinkingBar)?;
+        self.set_bracketed_paste(true)?;
+        self.set_mouse_capture(true)?;
+        terminal::enable_raw_mode()?;
         loop {
             let old_height = self.height;
-            self.dispatch_key_event()?; // This might alter `self.height`
-            self.render_ui(old_height)?;
+            let old_state = self.state.name();
+            let result = self.dispatch_key_event() // This might alter `self.height`
+                .map(|()| self.log_state_transition(old_state))
+                .and_then(|()| self.render_ui(old_height));
+            if let Err(err) = result {
+                // Leave the terminal usable for reporting the error
+                self.guard.restore(&mut self.sink);
+                return Err(err);
+            }
+        }
+    }
+
+    /// The number of lines of the debug overlay.
+    const DEBUG_OVERLAY_HEIGHT: u16 = 2;
+
+    /// A description of the key bindings handled by `dispatch_key_event()`.
+    /// Each of these keys is checked to be bound by `Repl::key_action()`.
+    const KEY_BINDINGS: &'static [(&'static str, &'static str)] = &[
+        ("Ctrl-D",              "Exit the REPL"),
+        ("Ctrl-Z",              "Suspend the REPL"),
+        ("Ctrl-G",              "Cancel history navigation, search or selection"),
+        ("Enter",               "Evaluate the cmd"),
+        ("Up, Ctrl-P",          "Move up a line, or to the previous history entry"),
+        ("Down, Ctrl-N",        "Move down a line, or to the next history entry"),
+        ("Left, Ctrl-B",        "Move left"),
+        ("Right, Ctrl-F",       "Move right"),
+        ("Home, Ctrl-A",        "Move to the start of the cmd"),
+        ("End, Ctrl-E",         "Move to the end of the cmd"),
+        ("Ctrl-R",              "Search backwards through the history"),
+        ("PageUp",              "Move to the previous history entry"),
+        ("PageDown",            "Move to the next history entry"),
+        ("Shift-Enter, Ctrl-O", "Insert a newline"),
+        ("Tab",                 "Insert a tab"),
+        ("Backspace",           "Delete the grapheme before the cursor"),
+        ("Delete",              "Delete the grapheme at the cursor"),
+        ("Ctrl-X Ctrl-E",       "Edit the cmd in $VISUAL or $EDITOR"),
+        ("Ctrl-X Ctrl-D",       "Toggle the debug overlay"),
+        ("Right, End",          "Accept the hint, at the end of the cmd"),
+        ("Alt-F",               "Accept the next word of the hint"),
+        ("Ctrl-Space",          "Set the mark, to select the region up to the cursor"),
+        ("Shift-<movement>",    "Extend the selection"),
+        ("Ctrl-W",              "Cut the selection"),
+        ("Alt-W",               "Copy the selection, or the whole cmd"),
+        ("Ctrl-Y",              "Paste the text on the clipboard"),
+    ];
+
+    /// Log a transition from the state called `old_state`, if any.
+    fn log_state_transition(&self, old_state: &str) {
+        let new_state = self.state.name();
+        if new_state != old_state {
+            log::debug!("state: {old_state} -> {new_state}");
+        }
+    }
+
+    /// Wait for the next terminal event.  Return `None` if the process was
+    /// suspended and resumed in the meantime, in which case the input area
+    /// needs to be redrawn.
+    fn read_event(&mut self) -> ReplBlockResult<Option<Event>> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(50);
+        if let Some(event) = self.typeahead.pop_front() {
+            return Ok(Some(event));
+        }
+        self.signals.set_reading(true);
+        let wakeup = loop {
+            if let Some(wakeup) = self.signals.take_wakeup() {
+                break wakeup;
+            }
+            if event::poll(POLL_INTERVAL)? {
+                let event = event::read()?;
+                self.signals.set_reading(false);
+                return Ok(Some(event));
+            }
+        };
+        self.signals.set_reading(false);
+        match wakeup {
+            Wakeup::Suspend => self.cmd_suspend()?,
+            Wakeup::Continued => self.resume()?,
+        }
+        Ok(None)
+    }
+
+    /// Describe `event` for logging, without any text that was typed or
+    /// pasted, as that can contain secrets.
+    fn describe_event(event: &Event) -> String {
+        match event {
+            Event::Key(key) => match key.code {
+                KeyCode::Char(_) if (key.modifiers - KeyModifiers::SHIFT).is_empty() => {
+                    format!("Key(Char, {:?})", key.kind)
+                }
+                _ => format!("Key({}, {:?})", binding::describe(key), key.kind),
+            },
+            Event::Paste(text) => format!("Paste({} bytes)", text.len()),
+            event => format!("{event:?}"),
         }
     }
 
     fn dispatch_key_event(&mut self) -> ReplBlockResult<()> {
-        terminal::enable_raw_mode()?;
-        let event = event::read()?;
-        terminal::disable_raw_mode()?;
+        let Some(event) = self.read_event()? else {
+            return Ok(());
+        };
+        log::trace!("event: {}", Self::describe_event(&event));
+        if let Some(asciicast) = &self.asciicast {
+            asciicast.record_event(&event)?;
+        }
+        let ctrl_x = std::mem::take(&mut self.ctrl_x_pending);
+        if let (Event::Key(key), false) = (event.clone(), ctrl_x) {
+            use KeyCode::{Down, End, Home, Left, Right, Up};
+            let is_movement = matches!(key.code, Left | Right | Up | Down | Home | End);
+            if is_movement && !key.modifiers.contains(KeyModifiers::SHIFT) && self.shift_selecting {
+                self.mark = None;
+            }
+            if key.kind == KeyEventKind::Press {
+                if let Some(idx) = self.key_bindings.iter().position(|kb| kb.matches(&key)) {
+                    log::debug!("action: custom key binding {}", binding::describe(&key));
+                    return self.cmd_custom_action(idx);
+                }
+            }
+        }
         match event {
-            Event::Key(key!(CONTROL-'c')) => self.cmd_nop()?,
+            Event::Key(key) => match Self::key_action(&key, ctrl_x) {
+                Some(action) => {
+                    log::debug!("action: {action:?}");
+                    self.perform(action)?;
+                }
+                None => match key {
+                    key!(@c) | key!(SHIFT-@c) if !ctrl_x => {
+                        log::debug!("action: InsertChar");
+                        self.cmd_insert_char(c)?;
+                    }
+                    _key => {/* ignore the key */},
+                },
+            },
+            Event::Paste(text) if !ctrl_x => {
+                log::debug!("action: Paste");
+                self.cmd_paste(&text)?;
+            }
+            Event::Mouse(mouse_event) if !ctrl_x => self.cmd_mouse(mouse_event)?,
+            _event => {/* ignore the event */},
+        }
+        if !matches!(self.state, State::Edit(_)) {
+            self.mark = None;
+        }
+        Ok(())
+    }
+
+    /// Return the built-in action that `key` is bound to, if any.  `ctrl_x`
+    /// indicates whether `key` is the second key of a `Ctrl-X` key chord.
+    fn key_action(key: &KeyEvent, ctrl_x: bool) -> Option<Action> {
+        if ctrl_x {
+            return match *key {
+                key!(CONTROL-'e') => Some(Action::EditInExternalEditor),
+                key!(CONTROL-'d') => Some(Action::ToggleDebugOverlay),
+                _key => None,
+            };
+        }
+        let action = match *key {
+            key!(CONTROL-'c') => Action::Nop,
+            key!(CONTROL-'x') => Action::StartCtrlXChord,
 
             // Control application lifecycle:
-            Event::Key(key!(CONTROL-'d')) => self.cmd_exit_repl()?,
-            Event::Key(key!(CONTROL-'g')) => self.cmd_cancel_nav()?,
-            Event::Key(key!(@name Enter)) => self.cmd_eval()?,
+            key!(CONTROL-'d') => Action::ExitRepl,
+            key!(CONTROL-'z') => Action::Suspend,
+            key!(CONTROL-'g') => Action::CancelNav,
+            key!(@name Enter) => Action::Eval,
 
             // Navigation:
-            Event::Key(key!(CONTROL-'p'))    => self.cmd_nav_up()?,
-            Event::Key(key!(@name Up))       => self.cmd_nav_up()?,
-            Event::Key(key!(CONTROL-'n'))    => self.cmd_nav_down()?,
-            Event::Key(key!(@name Down))     => self.cmd_nav_down()?,
-            Event::Key(key!(CONTROL-'b'))    => self.cmd_nav_cmd_left()?,
-            Event::Key(key!(@name Left))     => self.cmd_nav_cmd_left()?,
-            Event::Key(key!(CONTROL-'f'))    => self.cmd_nav_cmd_right()?,
-            Event::Key(key!(@name Right))    => self.cmd_nav_cmd_right()?,
-            Event::Key(key!(CONTROL-'a'))    => self.cmd_nav_to_start_of_cmd()?,
-            Event::Key(key!(@name Home))     => self.cmd_nav_to_start_of_cmd()?,
-            Event::Key(key!(CONTROL-'e'))    => self.cmd_nav_to_end_of_cmd()?,
-            Event::Key(key!(@name End))      => self.cmd_nav_to_end_of_cmd()?,
-            Event::Key(key!(CONTROL-'r'))    => self.cmd_reverse_search_history()?,
-            Event::Key(key!(@name PageUp))   => self.cmd_nav_history_up()?,
-            Event::Key(key!(@name PageDown)) => self.cmd_nav_history_down()?,
+            key!(CONTROL-'p')    => Action::NavUp,
+            key!(@name Up)       => Action::NavUp,
+            key!(CONTROL-'n')    => Action::NavDown,
+            key!(@name Down)     => Action::NavDown,
+            key!(CONTROL-'b')    => Action::NavLeft,
+            key!(@name Left)     => Action::NavLeft,
+            key!(CONTROL-'f')    => Action::NavRight,
+            key!(@name Right)    => Action::NavRight,
+            key!(CONTROL-'a')    => Action::NavToStartOfCmd,
+            key!(@name Home)     => Action::NavToStartOfCmd,
+            key!(CONTROL-'e')    => Action::NavToEndOfCmd,
+            key!(@name End)      => Action::NavToEndOfCmd,
+            key!(ALT-'f')        => Action::AcceptHintWord,
+            key!(CONTROL-'r')    => Action::ReverseSearchHistory,
+            key!(@name PageUp)   => Action::NavHistoryUp,
+            key!(@name PageDown) => Action::NavHistoryDown,
+
+            // Selection:
+            key!(CONTROL-' ')         => Action::SetMark,
+            key!(@name SHIFT-Left)    => Action::SelectLeft,
+            key!(@name SHIFT-Right)   => Action::SelectRight,
+            key!(@name SHIFT-Up)      => Action::SelectUp,
+            key!(@name SHIFT-Down)    => Action::SelectDown,
+            key!(@name SHIFT-Home)    => Action::SelectToStartOfCmd,
+            key!(@name SHIFT-End)     => Action::SelectToEndOfCmd,
+            key!(CONTROL-'w')         => Action::CutRegion,
+            key!(ALT-'w')             => Action::CopyRegion,
+            key!(CONTROL-'y')         => Action::Yank,
 
             // Editing;
-            Event::Key(key!(@c))                => self.cmd_insert_char(c)?,
-            Event::Key(key!(SHIFT-@c))          => self.cmd_insert_char(c)?,
             // FIXME `SHIFT+Enter` doesn't work for...reasons(??),
             //       yet `CONTROL-o` works as expected:
-            Event::Key(key!(@name SHIFT-Enter)) => self.cmd_insert_newline()?,
-            Event::Key(key!(CONTROL-'o'))       => self.cmd_insert_newline()?,
-            Event::Key(key!(@name Backspace))   => self.cmd_rm_grapheme_before_cursor()?,
-            Event::Key(key!(@name Delete))      => self.cmd_rm_grapheme_at_cursor()?,
+            key!(@name SHIFT-Enter) => Action::InsertNewline,
+            key!(CONTROL-'o')       => Action::InsertNewline,
+            key!(@name Tab)         => Action::InsertTab,
+            key!(@name Backspace)   => Action::RmGraphemeBeforeCursor,
+            key!(@name Delete)      => Action::RmGraphemeAtCursor,
 
-            _event => {/* ignore the event */},
+            _key => return None,
+        };
+        Some(action)
+    }
+
+    /// Perform the built-in `action`.
+    fn perform(&mut self, action: Action) -> ReplBlockResult<()> {
+        match action {
+            Action::Nop                    => self.cmd_nop(),
+            Action::StartCtrlXChord        => {
+                self.ctrl_x_pending = true;
+                Ok(())
+            }
+            Action::EditInExternalEditor   => self.cmd_edit_in_external_editor(),
+            Action::ToggleDebugOverlay     => self.cmd_toggle_debug_overlay(),
+            Action::ExitRepl               => self.cmd_exit_repl(),
+            Action::Suspend                => self.cmd_suspend(),
+            Action::CancelNav              => self.cmd_cancel_nav(),
+            Action::Eval                   => self.cmd_eval(),
+            Action::NavUp                  => self.cmd_nav_up(),
+            Action::NavDown                => self.cmd_nav_down(),
+            Action::NavLeft                => self.cmd_nav_cmd_left(),
+            Action::NavRight               => self.cmd_nav_cmd_right(),
+            Action::NavToStartOfCmd        => self.cmd_nav_to_start_of_cmd(),
+            Action::NavToEndOfCmd          => self.cmd_nav_to_end_of_cmd(),
+            Action::AcceptHintWord         => self.cmd_accept_hint_word(),
+            Action::ReverseSearchHistory   => self.cmd_reverse_search_history(),
+            Action::NavHistoryUp           => self.cmd_nav_history_up(),
+            Action::NavHistoryDown         => self.cmd_nav_history_down(),
+            Action::SetMark                => self.cmd_set_mark(),
+            Action::SelectLeft             => self.cmd_select(Self::cmd_nav_cmd_left),
+            Action::SelectRight            => self.cmd_select(Self::cmd_nav_cmd_right),
+            Action::SelectUp               => self.cmd_select(Self::cmd_nav_up),
+            Action::SelectDown             => self.cmd_select(Self::cmd_nav_down),
+            Action::SelectToStartOfCmd     => self.cmd_select(Self::cmd_nav_to_start_of_cmd),
+            Action::SelectToEndOfCmd       => self.cmd_select(Self::cmd_nav_to_end_of_cmd),
+            Action::CutRegion              => self.cmd_cut_region(),
+            Action::CopyRegion             => self.cmd_copy(),
+            Action::Yank                   => self.cmd_yank(),
+            Action::InsertNewline          => self.cmd_insert_newline(),
+            Action::InsertTab              => self.cmd_insert_char('\t'),
+            Action::RmGraphemeBeforeCursor => self.cmd_rm_grapheme_before_cursor(),
+            Action::RmGraphemeAtCursor     => self.cmd_rm_grapheme_at_cursor(),
         }
-        Ok(())
     }
 
     fn render_ui(&mut self, old_input_area_height: u16) -> ReplBlockResult<()> {
+        self.update_hint();
         let dims = self.input_area_dims()?;
-        let prompt_len = self.prompt_len();
-
-        let calculate_uncursor = |cmd: &Cmd, uncompressed: &Cmd, cursor: Coords| {
-            let prev_unlines: Vec<Vec<Line>> = (0..cursor.y)
-                .map(|y| cmd[y].uncompress(dims.width, prompt_len))
-                .collect();
-            let mut uncursor = Coords {
-                x: cursor.x,
-                y: prev_unlines.iter()
-                    .map(|unline| unline.len())
-                    .sum::<usize>() as u16,
-            };
-            let line = &cmd[cursor.y];
-            let unlines_for_line = line.uncompress(dims.width, prompt_len);
-            for unline in unlines_for_line.iter() {
-                let unline_len = unline.count_graphemes();
-                let width = std::cmp::min(dims.width, unline_len);
-                if uncursor.x > width {
-                    uncursor.x -= width;
-                    uncursor.y += 1;
-                } else {
-                    break;
-                }
-            }
-            if uncompressed[uncursor.y].is_start() {
-                uncursor.x += prompt_len;
-            }
-            uncursor
-        };
+        let (term_width, term_height) = terminal::size()?;
+        let default_prompt_len = self.default_prompt.width();
+        let continue_prompt_len = self.continue_prompt.width();
+        let tab_width = self.tabs.width();
 
         macro_rules! render {
             ($cmd:expr, $cursor:expr) => {{
                 let (cmd, cursor): (&Cmd, Coords) = ($cmd, $cursor);
-                let uncompressed = cmd.uncompress(dims.width, prompt_len);
-
-                // Adjust the height of the input area
-                let num_unlines = uncompressed.count_lines() as u16;
-                let content_height = num_unlines;
+                let mut highlights = bracket_highlights(cmd, cursor, self.theme.matching_bracket);
+                if let Some((start, end)) = self.region() {
+                    let style = self.theme.selection.to_content_style();
+                    highlights.push(Highlight { start, end, style });
+                }
+                let uncompressed = cmd.uncompress(
+                    dims.width,
+                    default_prompt_len,
+                    continue_prompt_len,
+                    tab_width,
+                );
+
+                // Adjust the height of the input area,
+                // but don't let it grow taller than the terminal
+                let num_unlines = uncompressed.count_lines();
+                let reserved = self.footer_height();
+                let content_height = num_unlines + reserved;
                 self.height = std::cmp::max(self.height, content_height);
+                self.height = std::cmp::min(self.height, term_height);
+                let rows = std::cmp::max(1, self.height.saturating_sub(reserved));
 
                 // Obtain an `uncompressed` version of `cursor`
-                let uncursor = calculate_uncursor(cmd, &uncompressed, cursor);
-
-                // Scroll up the old output *BEFORE* clearing the input area
-                for _ in old_input_area_height..content_height {
-                    queue!(self.sink, terminal::ScrollUp(1))?;
+                let uncursor = cmd.uncompress_cursor(
+                    cursor,
+                    dims.width,
+                    default_prompt_len,
+                    continue_prompt_len,
+                    tab_width,
+                );
+
+                // Scroll the input area internally to keep the uncursor visible
+                self.scroll_to(uncursor.y, rows, num_unlines);
+                let scroll = self.scroll;
+
+                // Scroll up the old output *BEFORE* drawing the input area
+                self.scroll_up(self.height.saturating_sub(old_input_area_height))?;
+
+                let info = RenderInfo {
+                    cursor,
+                    uncursor,
+                    scroll,
+                    rows,
+                    num_unlines,
+                    term_width,
+                    term_height,
+                };
+                log::trace!("render: height {}, {info:?}", self.height);
+                let mut frame = Frame::new(dims.width, self.height);
+                self.render_cmd(&mut frame, &uncompressed, scroll, rows, &highlights);
+                self.render_scroll_indicators(&mut frame, scroll, rows, num_unlines);
+                self.render_debug_overlay(&mut frame, &info);
+                self.render_status_line(&mut frame);
+
+                // Render the right prompt, unless it would collide with the
+                // first line of the cmd or that line is scrolled out of view
+                let mut right_prompt_len = 0;
+                if let (Some(right_prompt), 0) = (&self.right_prompt, scroll) {
+                    const MIN_GAP: u16 = 1;
+                    let first_line_len = default_prompt_len
+                        + uncompressed[0usize].display_width(0, tab_width);
+                    let col = dims.width.saturating_sub(right_prompt.width());
+                    if first_line_len + MIN_GAP <= col && !right_prompt.is_empty() {
+                        frame.move_to(col, 0);
+                        for part in right_prompt.iter() {
+                            frame.print_styled(part);
+                        }
+                        right_prompt_len = right_prompt.width() + MIN_GAP;
+                    }
                 }
 
-                // execute!(
-                //     self.sink,
-                //     cursor::MoveUp(terminal::size().unwrap().1),
-                //     cursor::MoveToColumn(0),
-                //     terminal::Clear(ClearType::All),
-                //     style::Print(format!("CMD: {cmd:#?}\n")),
-                //     style::Print(format!("UNCOMPRESSED: {uncompressed:#?}\n")),
-                //     style::Print(format!("CURSOR: {cursor}\n")),
-                //     style::Print(format!("UNCURSOR: {uncursor}\n")),
-                //     style::Print(format!("TERM DIMS: {:?}\n", terminal::size()?)),
-                //     style::Print(format!("INPUT AREA DIMS: {dims:?}\n")),
-                //     cursor::MoveDown(terminal::size().unwrap().1),
-                // )?;
-
-                self.clear_input_area()?;
-                self.move_cursor_to_origin()?;
-                self.render_cmd(&uncompressed)?;
+                // Render the hint, if any, after the uncursor
+                if let Some(hint) = &self.hint {
+                    let width = if uncursor.y == 0 {
+                        dims.width - right_prompt_len
+                    } else {
+                        dims.width
+                    };
+                    let room = width.saturating_sub(uncursor.x) as usize;
+                    let visible: String = hint.lines().next().unwrap_or("")
+                        .graphemes(true)
+                        .take(room)
+                        .collect();
+                    frame.move_to(uncursor.x, uncursor.y - scroll);
+                    frame.print_styled(&self.theme.hint.apply(visible));
+                }
 
                 // Render the uncursor
-                let o = self.origin()?;
-                queue!(self.sink, cursor::MoveToColumn(o.x + uncursor.x))?;
-                queue!(self.sink, cursor::MoveToRow(o.y + uncursor.y))?;
+                frame.set_cursor(uncursor.x, uncursor.y - scroll);
+                self.screen.draw(frame, term_height, &mut self.sink)?;
 
                 ReplBlockResult::Ok(())
             }};
@@ -341,32 +962,54 @@ impl<'eval, W: Write> Repl<'eval, W> {
             }
             State::Search(SearchState { regex, preview, cursor, .. }) => {
                 let (cmd, cursor): (&Cmd, Coords) = (preview, *cursor);
-                let uncompressed = cmd.uncompress(dims.width, prompt_len);
+                let uncompressed = cmd.uncompress(
+                    dims.width,
+                    default_prompt_len,
+                    continue_prompt_len,
+                    tab_width,
+                );
                 let regex = regex.clone();
+                let highlights = search_highlights(cmd, &regex, self.theme.search_match);
 
-                // Adjust the height of the input area
-                let num_unlines = uncompressed.count_lines() as u16;
+                // Adjust the height of the input area,
+                // but don't let it grow taller than the terminal
+                let num_unlines = uncompressed.count_lines();
                 const SEARCH_PROMPT_LINE: u16 = 1;
-                let content_height = num_unlines + SEARCH_PROMPT_LINE;
+                let reserved = SEARCH_PROMPT_LINE + self.footer_height();
+                let content_height = num_unlines + reserved;
                 self.height = std::cmp::max(self.height, content_height);
-
-                // Scroll up the old output *BEFORE* clearing the input area
-                for _ in old_input_area_height..content_height {
-                    queue!(self.sink, terminal::ScrollUp(1))?;
-                }
-
-                self.clear_input_area()?;
-                self.move_cursor_to_origin()?;
-                self.render_cmd(&uncompressed)?;
-                self.render_reverse_search_prompt()?;
+                self.height = std::cmp::min(self.height, term_height);
+                let rows = std::cmp::max(1, self.height.saturating_sub(reserved));
+
+                // Scroll up the old output *BEFORE* drawing the input area
+                self.scroll_up(self.height.saturating_sub(old_input_area_height))?;
+
+                // The preview is always shown from its first line onwards
+                self.scroll = 0;
+                let info = RenderInfo {
+                    cursor,
+                    uncursor: cursor,
+                    scroll: self.scroll,
+                    rows,
+                    num_unlines,
+                    term_width,
+                    term_height,
+                };
+                log::trace!("render: height {}, {info:?}", self.height);
+                let mut frame = Frame::new(dims.width, self.height);
+                self.render_cmd(&mut frame, &uncompressed, self.scroll, rows, &highlights);
+                self.render_scroll_indicators(&mut frame, self.scroll, rows, num_unlines);
+                self.render_debug_overlay(&mut frame, &info);
+                self.render_status_line(&mut frame);
+                self.render_reverse_search_prompt(&mut frame);
 
                 // Render the reverse search topic
-                queue!(self.sink, style::Print(regex))?;
+                frame.print(regex);
 
-                let o = self.origin()?;
                 // Render the search prompt cursor
-                queue!(self.sink, cursor::MoveToRow(o.y + cursor.y + self.height))?;
-                queue!(self.sink, cursor::MoveToColumn(o.x + cursor.x))?;
+                let row = self.height.saturating_sub(reserved);
+                frame.set_cursor(cursor.x, cursor.y + row);
+                self.screen.draw(frame, term_height, &mut self.sink)?;
             }
         }
 
@@ -374,58 +1017,202 @@ impl<'eval, W: Write> Repl<'eval, W> {
         Ok(())
     }
 
-    fn render_cmd(&mut self, uncompressed: &Cmd, ) -> ReplBlockResult<()> {
+    /// Ask the hinter, if any, for a hint for the cmd being edited.
+    /// Hints are only shown while the cursor is at the end of the cmd.
+    fn update_hint(&mut self) {
+        self.hint = match (&mut self.hinter, &self.state) {
+            (Some(hinter), State::Edit(EditState { buffer, cursor }))
+                if *cursor == buffer.end_of_cmd() =>
+            {
+                let history = HistoryView::new(&self.history);
+                hinter.hint(&buffer.to_source_code(), &history)
+                    .filter(|hint| !hint.is_empty())
+            }
+            _ => None,
+        };
+    }
+
+    /// Adjust `self.scroll` such that the uncompressed line `unline_idx` is
+    /// visible in an input area that shows `rows` out of `num_unlines` lines.
+    fn scroll_to(&mut self, unline_idx: u16, rows: u16, num_unlines: u16) {
+        if unline_idx < self.scroll {
+            self.scroll = unline_idx;
+        } else if unline_idx >= self.scroll + rows {
+            self.scroll = unline_idx + 1 - rows;
+        }
+        let max_scroll = num_unlines.saturating_sub(rows);
+        self.scroll = std::cmp::min(self.scroll, max_scroll);
+    }
+
+    /// Render `rows` lines of an `uncompressed` cmd, starting at the line
+    /// with index `scroll`.  The graphemes covered by any of the `highlights`
+    /// are rendered in the style of that highlight.
+    fn render_cmd(
+        &self,
+        frame: &mut Frame,
+        uncompressed: &Cmd,
+        scroll: u16,
+        rows: u16,
+        highlights: &[Highlight],
+    ) {
+        // The position in the compressed cmd of the first grapheme of an unline
+        let mut pos = ORIGIN;
+        // The column in the compressed line of the first grapheme of an unline
+        let mut col = 0;
+        let tab_width = self.tabs.width();
         for (ulidx, unline) in uncompressed.lines().iter().enumerate() {
-            if ulidx == 0 {
-                self.render_default_prompt()?;
-                queue!(self.sink, style::Print(unline))?;
-                queue!(self.sink, cursor::MoveDown(1))?;
-                queue!(self.sink, cursor::MoveToColumn(0))?;
-            } else if unline.is_start() {
-                self.render_continue_prompt()?;
-                queue!(self.sink, style::Print(unline))?;
-                queue!(self.sink, cursor::MoveDown(1))?;
-                // queue!(self.sink, cursor::MoveToColumn(0))?;
-            } else {
-                queue!(self.sink, style::Print(unline))?;
-                queue!(self.sink, cursor::MoveDown(1))?;
-                queue!(self.sink, cursor::MoveToColumn(0))?;
+            if ulidx > 0 && unline.is_start() {
+                pos = Coords { x: 0, y: pos.y + 1 };
+            }
+            if unline.is_start() {
+                col = 0;
             }
+            let is_visible = (scroll as usize..(scroll + rows) as usize).contains(&ulidx);
+            if is_visible {
+                if ulidx == 0 {
+                    self.render_default_prompt(frame);
+                } else if unline.is_start() {
+                    self.render_continue_prompt(frame);
+                }
+                self.render_unline(frame, unline, pos, col, highlights);
+                frame.next_line();
+            }
+            pos.x += unline.count_graphemes();
+            col += unline.display_width(col, tab_width);
         }
-        Ok(())
     }
 
-    fn render_default_prompt(
-        &mut self,
-    ) -> ReplBlockResult<&mut Self> {
-        queue!(self.sink, cursor::MoveToColumn(0))?;
-        for &c in &self.default_prompt {
-            queue!(self.sink, style::Print(c))?;
+    /// Render a single `unline`, whose first grapheme is located at `pos` in
+    /// the compressed cmd, and at column `col` of the compressed line.
+    /// Tabs are rendered as whitespace, and control characters in caret
+    /// notation e.g. `^C`.
+    fn render_unline(
+        &self,
+        frame: &mut Frame,
+        unline: &Line,
+        pos: Coords,
+        mut col: u16,
+        highlights: &[Highlight],
+    ) {
+        let is_plain = !unline.chars().any(char::is_control);
+        if highlights.is_empty() && is_plain {
+            frame.print(unline);
+            return;
+        }
+        let tab_width = self.tabs.width();
+        let control_style = self.theme.indicator.to_content_style();
+        for (gidx, grapheme) in unline.graphemes().enumerate() {
+            let gpos = Coords { x: pos.x + gidx as u16, y: pos.y };
+            let displayed = cmd::display_grapheme(grapheme, col, tab_width);
+            col += cmd::display_width(grapheme, col, tab_width);
+            let style = match highlights.iter().rev().find(|h| h.contains(gpos)) {
+                Some(highlight) => highlight.style,
+                None if grapheme != "\t" && displayed != grapheme => control_style,
+                None => {
+                    frame.print(displayed);
+                    continue;
+                }
+            };
+            frame.print_styled(&style.apply(displayed));
         }
-        Ok(self)
     }
 
-    fn render_continue_prompt(
-        &mut self,
-    ) -> ReplBlockResult<()> {
-        queue!(self.sink, cursor::MoveToColumn(0))?;
-        for &c in &self.continue_prompt {
-            queue!(self.sink, style::Print(c))?;
+    fn render_default_prompt(&self, frame: &mut Frame) {
+        frame.move_to_column(0);
+        for part in self.default_prompt.iter() {
+            frame.print_styled(part);
         }
-        Ok(())
     }
 
-    fn render_reverse_search_prompt(
-        &mut self,
-    ) -> ReplBlockResult<()> {
-        let origin = self.origin()?;
-        // Position the cursor to write the reverse search prompt
-        queue!(self.sink, cursor::MoveTo(origin.x, origin.y + self.height))?;
-        // Render the reverse search prompt
-        for c in &self.reverse_search_prompt {
-            queue!(self.sink, style::Print(c))?;
+    fn render_continue_prompt(&self, frame: &mut Frame) {
+        frame.move_to_column(0);
+        for part in self.continue_prompt.iter() {
+            frame.print_styled(part);
+        }
+    }
+
+    fn render_reverse_search_prompt(&self, frame: &mut Frame) {
+        // Position the pen to draw the reverse search prompt,
+        // on the last line of the input area above the status line
+        let row = self.height.saturating_sub(1 + self.footer_height());
+        frame.move_to(0, row);
+        for part in self.reverse_search_prompt.iter() {
+            frame.print_styled(part);
+        }
+    }
+
+    /// Indicate whether there are uncompressed lines above and/or below the
+    /// ones that are visible in the input area.
+    fn render_scroll_indicators(
+        &self,
+        frame: &mut Frame,
+        scroll: u16,
+        rows: u16,
+        num_unlines: u16,
+    ) {
+        let col = frame.width().saturating_sub(1);
+        if scroll > 0 {
+            frame.move_to(col, 0);
+            frame.print_styled(&self.theme.indicator.apply('▲'));
+        }
+        if scroll + rows < num_unlines {
+            frame.move_to(col, rows - 1);
+            frame.print_styled(&self.theme.indicator.apply('▼'));
+        }
+    }
+
+    /// Render the status line, if any, on the last line of the input area.
+    fn render_status_line(&self, frame: &mut Frame) {
+        let Some(status_line) = &self.status_line else {
+            return;
+        };
+        frame.move_to(0, self.height.saturating_sub(1));
+        for part in status_line.iter() {
+            frame.print_styled(part);
+        }
+    }
+
+    /// Render the debug overlay, if it's enabled, above the status line.
+    fn render_debug_overlay(&self, frame: &mut Frame, info: &RenderInfo) {
+        if !self.debug_overlay {
+            return;
+        }
+        let lines = [
+            format!(
+                "state: {}  cursor: {}  uncursor: {}  scroll: {}",
+                self.state.name(), info.cursor, info.uncursor, info.scroll,
+            ),
+            format!(
+                "term: {}x{}  input area: {}x{}  unlines: {}  rows: {}",
+                info.term_width, info.term_height, frame.width(), frame.height(),
+                info.num_unlines, info.rows,
+            ),
+        ];
+        // On terminals that are too short for it, the overlay isn't shown
+        let Some(row) = self.height.checked_sub(self.footer_height()) else {
+            return;
+        };
+        for (idx, line) in lines.iter().enumerate() {
+            frame.move_to(0, row + idx as u16);
+            frame.print_styled(&self.theme.indicator.apply(line));
         }
-        Ok(())
+    }
+
+    /// Return the number of lines reserved for the status line.
+    fn status_line_height(&self) -> u16 {
+        self.status_line.is_some() as u16
+    }
+
+    /// Return the number of lines reserved underneath the cmd, for the
+    /// debug overlay and the status line.
+    fn footer_height(&self) -> u16 {
+        let overlay_height = if self.debug_overlay { Self::DEBUG_OVERLAY_HEIGHT } else { 0 };
+        overlay_height + self.status_line_height()
+    }
+
+    /// Return the minimum height of the input area, in lines.
+    fn min_height(&self) -> u16 {
+        1 + self.footer_height()
     }
 
     fn move_cursor_to_origin(
@@ -439,6 +1226,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
     fn clear_input_area(
         &mut self,
     ) -> ReplBlockResult<()> {
+        self.screen.invalidate();
         self.move_cursor_to_origin()?;
         for _ in 0..self.height {
             queue!(self.sink, terminal::Clear(ClearType::CurrentLine))?;
@@ -452,7 +1240,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
     /// Return the global (col, row)-coordinates of the top-left corner of `self`.
     fn origin(&self) -> ReplBlockResult<Coords> {
         let (_term_width, term_height) = terminal::size()?;
-        Ok(Coords { x: 0, y: term_height - self.height })
+        Ok(Coords { x: 0, y: term_height.saturating_sub(self.height) })
     }
 
     /// Return the (width, height) dimensions of `self`.
@@ -462,15 +1250,414 @@ impl<'eval, W: Write> Repl<'eval, W> {
         Ok(Dims { width: term_width, height: self.height })
     }
 
-    fn prompt_len(&self) -> u16 {
-        assert_eq!(
-            self.default_prompt.len(), self.continue_prompt.len(),
-            "default_prompt.len() != continue_prompt.len()"
-        );
-        self.default_prompt.len() as u16
+    /// Render the default and continuation prompts for the next cmd.
+    fn refresh_prompts(&mut self) {
+        let ctx = PromptContext {
+            cmd_count: self.cmd_count,
+            last_status: self.last_status,
+            last_duration: self.last_duration,
+        };
+        let caps = &self.term_capabilities;
+        self.default_prompt = self.prompt.default_prompt(&ctx).downgrade(caps);
+        self.continue_prompt = self.prompt.continue_prompt(&ctx).downgrade(caps);
+        self.right_prompt = self.prompt.right_prompt(&ctx)
+            .map(|prompt| prompt.downgrade(caps));
+        self.status_line = self.prompt.status_line(&ctx)
+            .map(|status_line| status_line.downgrade(caps));
+    }
+
+    /// Set the shape of the cursor, if the terminal supports that.
+    fn set_cursor_style(&mut self, style: cursor::SetCursorStyle) -> ReplBlockResult<()> {
+        if self.term_capabilities.supports_cursor_styles() {
+            let is_default = matches!(style, cursor::SetCursorStyle::DefaultUserShape);
+            queue!(self.sink, style)?;
+            self.guard.set_cursor_style(!is_default);
+        }
+        Ok(())
+    }
+
+    /// Enable or disable bracketed paste, if the terminal supports that.
+    /// With bracketed paste enabled, pasted text arrives as a single event
+    /// rather than as individual key presses.
+    fn set_bracketed_paste(&mut self, enabled: bool) -> ReplBlockResult<()> {
+        if self.term_capabilities.dumb {
+            return Ok(());
+        }
+        if enabled {
+            queue!(self.sink, event::EnableBracketedPaste)?;
+        } else {
+            queue!(self.sink, event::DisableBracketedPaste)?;
+        }
+        self.guard.set_bracketed_paste(enabled);
+        Ok(())
+    }
+
+    /// Enable or disable mouse capture, if it was requested and the terminal
+    /// supports it.
+    fn set_mouse_capture(&mut self, enabled: bool) -> ReplBlockResult<()> {
+        if !self.mouse_capture || self.term_capabilities.dumb {
+            return Ok(());
+        }
+        if enabled {
+            queue!(self.sink, event::EnableMouseCapture)?;
+        } else {
+            queue!(self.sink, event::DisableMouseCapture)?;
+        }
+        self.guard.set_mouse_capture(enabled);
+        Ok(())
+    }
+
+    /// Scroll the terminal contents up by `lines` lines, to make room for
+    /// the input area.  On terminals that don't support scroll sequences,
+    /// newlines are emitted on the last row of the terminal instead.
+    fn scroll_up(&mut self, lines: u16) -> ReplBlockResult<()> {
+        if lines == 0 {
+            return Ok(());
+        }
+        if self.term_capabilities.supports_scrolling() {
+            queue!(self.sink, terminal::ScrollUp(lines))?;
+        } else {
+            let (_term_width, term_height) = terminal::size()?;
+            queue!(self.sink, cursor::MoveTo(0, term_height.saturating_sub(1)))?;
+            for _ in 0..lines {
+                queue!(self.sink, style::Print("\n"))?;
+            }
+        }
+        Ok(())
+    }
+
+
+
+    /// Return the selected region of the cmd being edited, if any, as a pair
+    /// of positions in which the first doesn't come after the second.
+    fn region(&self) -> Option<(Coords, Coords)> {
+        let (State::Edit(EditState { cursor, .. }), Some(mark)) = (&self.state, self.mark) else {
+            return None;
+        };
+        let key = |c: Coords| (c.y, c.x);
+        if key(mark) <= key(*cursor) {
+            Some((mark, *cursor))
+        } else {
+            Some((*cursor, mark))
+        }
+    }
+
+    /// Remove the selected region, if any, from the cmd being edited and
+    /// move the cursor to where it was.  Return the removed text.
+    fn remove_region(&mut self) -> Option<String> {
+        let (start, end) = self.region()?;
+        self.mark = None;
+        let State::Edit(EditState { buffer, cursor }) = &mut self.state else {
+            return None;
+        };
+        let text = buffer.text_between(start, end);
+        buffer.remove_range(start, end);
+        *cursor = start;
+        Some(text)
+    }
+
+    /// Prepare for inserting text.  A region that was selected with
+    /// `Shift` and movement keys is replaced by the inserted text, and any
+    /// other region is deselected.
+    fn replace_region(&mut self) {
+        if self.shift_selecting {
+            self.remove_region();
+        }
+        self.mark = None;
+    }
+
+    /// Set the mark at the cursor, or unset it if it's already there.
+    fn cmd_set_mark(&mut self) -> ReplBlockResult<()> {
+        let State::Edit(EditState { cursor, .. }) = &self.state else {
+            return Ok(()); // NOP
+        };
+        self.mark = if self.mark == Some(*cursor) { None } else { Some(*cursor) };
+        self.shift_selecting = false;
+        Ok(())
+    }
+
+    /// Extend the selection by moving the cursor with `nav`.
+    fn cmd_select(
+        &mut self,
+        nav: fn(&mut Self) -> ReplBlockResult<()>,
+    ) -> ReplBlockResult<()> {
+        if let State::Navigate(NavigateState { preview, cursor, .. }) = &mut self.state {
+            self.state = State::Edit(EditState {
+                buffer: std::mem::take(preview),
+                cursor: *cursor,
+            });
+        }
+        let State::Edit(EditState { cursor, .. }) = &self.state else {
+            return Ok(()); // NOP
+        };
+        if self.mark.is_none() || !self.shift_selecting {
+            self.mark = Some(*cursor);
+            self.shift_selecting = true;
+        }
+        let mark = self.mark;
+        // Don't let e.g. `Shift-Right` accept the hint at the end of the cmd.
+        // The hint is updated anyway before it's rendered again.
+        self.hint = None;
+        nav(self)?;
+        // Moving past the first or last line navigates the history
+        if matches!(self.state, State::Edit(_)) {
+            self.mark = mark;
+        }
+        Ok(())
+    }
+
+    /// Store `text` on the clipboard.
+    fn copy_to_clipboard(&mut self, text: &str) -> ReplBlockResult<()> {
+        if self.term_capabilities.dumb {
+            // Don't emit escape sequences that the terminal doesn't support
+            self.clipboard.set_contents(text, &mut std::io::sink())?;
+        } else {
+            self.clipboard.set_contents(text, &mut self.sink)?;
+        }
+        Ok(())
+    }
+
+    /// Insert `text` at the cursor, replacing the selected region if any.
+    fn insert_text(&mut self, text: &str) {
+        if let State::Navigate(NavigateState { preview, cursor, .. }) = &mut self.state {
+            self.state = State::Edit(EditState {
+                buffer: std::mem::take(preview),
+                cursor: *cursor,
+            });
+        }
+        self.replace_region();
+        if let State::Edit(EditState { buffer, cursor }) = &mut self.state {
+            // Tab stops are relative to the start of the line
+            let col = buffer[cursor.y].graphemes()
+                .take(cursor.x as usize)
+                .fold(0, |col, g| col + cmd::display_width(g, col, self.tabs.width()));
+            *cursor = buffer.insert_str(*cursor, &self.tabs.expand(text, col));
+        }
     }
 
+    /// Run the action of the custom key binding at index `idx` on the cmd
+    /// being edited.  A history entry or search result that's being viewed
+    /// is edited instead.
+    fn cmd_custom_action(&mut self, idx: usize) -> ReplBlockResult<()> {
+        match &mut self.state {
+            State::Edit(EditState { .. }) => {}
+            State::Navigate(NavigateState { preview, cursor, .. }) => {
+                self.state = State::Edit(EditState {
+                    buffer: std::mem::take(preview),
+                    cursor: *cursor,
+                });
+            }
+            State::Search(SearchState { preview, .. }) => {
+                let buffer = std::mem::take(preview);
+                self.state = State::Edit(EditState {
+                    cursor: buffer.end_of_cmd(),
+                    buffer,
+                });
+            }
+        }
+        self.mark = None;
+        let State::Edit(EditState { buffer, cursor }) = &mut self.state else {
+            unreachable!("[Repl::cmd_custom_action] not in edit mode");
+        };
+        let result = (self.key_bindings[idx].action)(buffer, cursor);
+        if buffer.is_empty() {
+            *buffer = Cmd::default();
+        }
+        *cursor = buffer.clamp_pos(*cursor);
+        result
+    }
 
+    /// Replace the cmd being edited with the text that the next prompt was
+    /// seeded with, if any.
+    fn apply_prompt_seed(&mut self) {
+        if let Some(text) = self.prompt_seed.take() {
+            let buffer = Cmd::from_source_code(&self.tabs.expand(&text, 0));
+            self.state = State::Edit(EditState {
+                cursor: buffer.end_of_cmd(),
+                buffer,
+            });
+        }
+    }
+
+    /// Apply the `action` that the evaluator returned, to the cmd that's
+    /// edited at the next prompt or to the screen.
+    fn apply_eval_action(&mut self, action: EvalAction) -> ReplBlockResult<()> {
+        match action {
+            EvalAction::None => {}
+            EvalAction::Edit(text) => {
+                self.prompt_seed.set(text);
+                self.apply_prompt_seed();
+            }
+            EvalAction::Insert(text) => self.insert_text(&text),
+            EvalAction::ClearScreen => self.clear_screen()?,
+        }
+        Ok(())
+    }
+
+    /// Clear the screen and the scrollback, and move the cursor to the top.
+    fn clear_screen(&mut self) -> ReplBlockResult<()> {
+        queue!(
+            self.sink,
+            terminal::Clear(ClearType::All),
+            terminal::Clear(ClearType::Purge),
+            cursor::MoveTo(0, 0),
+        )?;
+        Ok(())
+    }
+
+    /// Cut the selected region.
+    fn cmd_cut_region(&mut self) -> ReplBlockResult<()> {
+        if let Some(text) = self.remove_region() {
+            self.copy_to_clipboard(&text)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the selected region and deselect it, or copy the whole cmd if
+    /// there is no selected region.
+    fn cmd_copy(&mut self) -> ReplBlockResult<()> {
+        let text = match (self.region(), &self.state) {
+            (Some((start, end)), State::Edit(EditState { buffer, .. })) => {
+                buffer.text_between(start, end)
+            }
+            (_, State::Edit(EditState { buffer, .. })) => buffer.to_source_code(),
+            (_, State::Navigate(NavigateState { preview, .. })) => preview.to_source_code(),
+            (_, State::Search(SearchState { preview, .. })) => preview.to_source_code(),
+        };
+        self.mark = None;
+        self.copy_to_clipboard(&text)
+    }
+
+    /// Insert the text on the clipboard at the cursor.
+    fn cmd_yank(&mut self) -> ReplBlockResult<()> {
+        if let Some(text) = self.clipboard.contents() {
+            self.insert_text(&text);
+        }
+        Ok(())
+    }
+
+    /// Insert text that was pasted into the terminal at the cursor.
+    fn cmd_paste(&mut self, text: &str) -> ReplBlockResult<()> {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        match &mut self.state {
+            State::Search(SearchState { .. }) => {
+                // Only the first line of the pasted text is used as regex
+                let first_line = text.lines().next().unwrap_or("");
+                for c in first_line.chars() {
+                    self.cmd_insert_char(c)?;
+                }
+            }
+            _ => self.insert_text(&text),
+        }
+        Ok(())
+    }
+
+    /// Map a cell on screen to a position in the cmd being edited or
+    /// previewed.  Return `None` if the cell is outside of the input area.
+    fn cell_to_coords(&self, column: u16, row: u16) -> ReplBlockResult<Option<Coords>> {
+        let cmd = match &self.state {
+            State::Edit(EditState { buffer, .. }) => buffer,
+            State::Navigate(NavigateState { preview, .. }) => preview,
+            State::Search(SearchState { .. }) => return Ok(None),
+        };
+        let dims = self.input_area_dims()?;
+        let origin = self.origin()?;
+        let rows = std::cmp::max(1, self.height.saturating_sub(self.footer_height()));
+        if row < origin.y || row >= origin.y + rows || column < origin.x {
+            return Ok(None);
+        }
+        let uncursor = Coords {
+            x: column - origin.x,
+            y: row - origin.y + self.scroll,
+        };
+        Ok(Some(cmd.compress_cursor(
+            uncursor,
+            dims.width,
+            self.default_prompt.width(),
+            self.continue_prompt.width(),
+            self.tabs.width(),
+        )))
+    }
+
+    /// Click to move the cursor, drag to select text,
+    /// and scroll to navigate the history.
+    fn cmd_mouse(&mut self, event: MouseEvent) -> ReplBlockResult<()> {
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some(pos) = self.cell_to_coords(event.column, event.row)? else {
+                    return Ok(());
+                };
+                if let State::Navigate(NavigateState { preview, .. }) = &mut self.state {
+                    self.state = State::Edit(EditState {
+                        buffer: std::mem::take(preview),
+                        cursor: pos,
+                    });
+                }
+                if let State::Edit(EditState { cursor, .. }) = &mut self.state {
+                    *cursor = pos;
+                }
+                self.mark = None;
+                self.mouse_anchor = Some(pos);
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                let Some(anchor) = self.mouse_anchor else { return Ok(()) };
+                let Some(pos) = self.cell_to_coords(event.column, event.row)? else {
+                    return Ok(());
+                };
+                if let State::Edit(EditState { cursor, .. }) = &mut self.state {
+                    *cursor = pos;
+                    self.mark = Some(anchor);
+                    self.shift_selecting = true;
+                }
+            }
+            MouseEventKind::Up(MouseButton::Left) => {
+                self.mouse_anchor = None;
+            }
+            MouseEventKind::ScrollUp => self.cmd_nav_history_up()?,
+            MouseEventKind::ScrollDown => self.cmd_nav_history_down()?,
+            _ => {/* ignore the event */},
+        }
+        Ok(())
+    }
+
+    /// Restore the terminal and suspend the process, as a shell would
+    /// with `Ctrl-Z`.  Once the process is resumed, set up the terminal for
+    /// the REPL again.
+    fn cmd_suspend(&mut self) -> ReplBlockResult<()> {
+        if !cfg!(unix) {
+            return Ok(()); // Job control isn't supported
+        }
+        // Leave the cmd on screen and move below the input area, so that the
+        // shell's output doesn't overwrite it
+        let (_term_width, term_height) = terminal::size()?;
+        self.set_cursor_style(cursor::SetCursorStyle::DefaultUserShape)?;
+        self.set_bracketed_paste(false)?;
+        self.set_mouse_capture(false)?;
+        queue!(self.sink, cursor::MoveTo(0, term_height.saturating_sub(1)))?;
+        queue!(self.sink, style::Print("\n"))?;
+        self.sink.flush()?;
+        terminal::disable_raw_mode()?;
+        suspend::suspend()?;
+        // Execution continues here after SIGCONT
+        self.signals.take_continued();
+        self.resume()
+    }
+
+    /// Set up the terminal for the REPL again after the process was
+    /// continued, and make room for redrawing the input area.
+    fn resume(&mut self) -> ReplBlockResult<()> {
+        // The shell may have reset the terminal while the process was stopped,
+        // and printed over the input area
+        self.screen.invalidate();
+        terminal::disable_raw_mode()?;
+        terminal::enable_raw_mode()?;
+        self.set_cursor_style(cursor::SetCursorStyle::BlinkingBar)?;
+        self.set_bracketed_paste(true)?;
+        self.set_mouse_capture(true)?;
+        self.scroll_up(self.height)?;
+        self.sink.flush()?;
+        Ok(())
+    }
 
     fn cmd_nop(&mut self) -> ReplBlockResult<()> {
         Ok(()) // NOP
@@ -478,21 +1665,24 @@ impl<'eval, W: Write> Repl<'eval, W> {
 
     /// Exit the REPL
     fn cmd_exit_repl(&mut self) -> ReplBlockResult<()> {
+        self.set_cursor_style(cursor::SetCursorStyle::DefaultUserShape)?;
+        self.set_bracketed_paste(false)?;
+        self.set_mouse_capture(false)?;
         execute!(
             self.sink,
-            cursor::SetCursorStyle::DefaultUserShape,
             cursor::MoveToColumn(0),
             style::Print(&self.goodbye_msg),
             terminal::Clear(ClearType::FromCursorDown),
         )?;
         self.sink.flush()?;
+        terminal::disable_raw_mode()?;
         std::process::exit(0);
     }
 
     fn cmd_cancel_nav(&mut self) -> ReplBlockResult<()> {
         match &mut self.state {
             State::Edit(EditState { .. }) => {
-                // NOP
+                self.mark = None; // Deselect the region, if any
             }
             State::Navigate(NavigateState { backup, .. }) => {
                 self.state = State::Edit(EditState {
@@ -590,7 +1780,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 }
             }
             State::Search(SearchState { preview, matches, current, .. }) => {
-                if *current >= matches.len() - 1 {
+                if *current + 1 >= matches.len() {
                     // NOP
                 } else {
                     *current += 1;
@@ -666,7 +1856,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 update_cursor(preview, cursor);
             },
             State::Search(SearchState { cursor, .. }) => {
-                let prompt_len = self.reverse_search_prompt.len() as u16;
+                let prompt_len = self.reverse_search_prompt.width();
                 if cursor.x <= prompt_len {
                     cursor.x = prompt_len; // bound here
                 } else {
@@ -678,6 +1868,9 @@ impl<'eval, W: Write> Repl<'eval, W> {
     }
 
     fn cmd_nav_cmd_right(&mut self) -> ReplBlockResult<()> {
+        if self.accept_hint(false) {
+            return Ok(());
+        }
         let update_cursor = |cmd: &Cmd, cursor: &mut Coords| {
             if *cursor == cmd.end_of_cmd() {
                 // NOP
@@ -705,7 +1898,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 update_cursor(preview, cursor);
             },
             State::Search(SearchState { regex, cursor, .. }) => {
-                let prompt_len = self.reverse_search_prompt.len() as u16;
+                let prompt_len = self.reverse_search_prompt.width();
                 let regex_line_len = regex.graphemes(true).count() as u16;
                 if cursor.x >= prompt_len + regex_line_len {
                     cursor.x = prompt_len + regex_line_len; // bound here
@@ -727,7 +1920,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 *cursor = ORIGIN;
             },
             State::Search(SearchState { cursor, .. }) => {
-                let prompt_len = self.reverse_search_prompt.len() as u16;
+                let prompt_len = self.reverse_search_prompt.width();
                 cursor.x = prompt_len;
             },
         }
@@ -736,6 +1929,9 @@ impl<'eval, W: Write> Repl<'eval, W> {
 
     /// Navigate to the end of the current Cmd
     fn cmd_nav_to_end_of_cmd(&mut self) -> ReplBlockResult<()> {
+        if self.accept_hint(false) {
+            return Ok(());
+        }
         match &mut self.state {
             State::Edit(EditState { buffer, cursor }) => {
                 *cursor = buffer.end_of_cmd();
@@ -744,7 +1940,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 *cursor = preview.end_of_cmd();
             },
             State::Search(SearchState { regex, cursor, .. }) => {
-                let prompt_len = self.reverse_search_prompt.len() as u16;
+                let prompt_len = self.reverse_search_prompt.width();
                 let regex_line_len = regex.graphemes(true).count() as u16;
                 cursor.x = prompt_len + regex_line_len;
             },
@@ -752,6 +1948,26 @@ impl<'eval, W: Write> Repl<'eval, W> {
         Ok(())
     }
 
+    /// Accept the next word of the current hint, if any.
+    fn cmd_accept_hint_word(&mut self) -> ReplBlockResult<()> {
+        self.accept_hint(true);
+        Ok(())
+    }
+
+    /// Insert the current hint, or just its first word, into the cmd being
+    /// edited.  Return whether or not there was a hint to accept.
+    fn accept_hint(&mut self, first_word_only: bool) -> bool {
+        let State::Edit(EditState { buffer, cursor }) = &mut self.state else {
+            return false;
+        };
+        let Some(hint) = self.hint.take() else {
+            return false;
+        };
+        let accepted = if first_word_only { hint::first_word(&hint) } else { &hint };
+        *cursor = buffer.insert_str(*cursor, accepted);
+        true
+    }
+
     fn cmd_reverse_search_history(&mut self) -> ReplBlockResult<()> {
         match &mut self.state {
             State::Edit(EditState { buffer, cursor }) => {
@@ -791,7 +2007,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 } else {
                     self.history[matches[*current]].clone()
                 };
-                let prompt_len = self.reverse_search_prompt.len() as u16;
+                let prompt_len = self.reverse_search_prompt.width();
                 *cursor = Coords { x: prompt_len, y: ORIGIN.y };
             }
         }
@@ -800,10 +2016,50 @@ impl<'eval, W: Write> Repl<'eval, W> {
 
     /// Insert a char into the current cmd at cursor position.
     fn cmd_insert_char(&mut self, c: char) -> ReplBlockResult<()> {
+        self.replace_region();
         let dims = self.input_area_dims()?;
         match &mut self.state {
+            State::Edit(EditState { .. }) if c == '\t' => {
+                self.insert_text("\t");
+            }
             State::Edit(EditState { buffer, cursor }) => {
-                buffer.insert_char(*cursor, c);
+                let typed = c.to_string();
+                let next = bracket::grapheme_at(buffer, *cursor);
+                let is_closer = bracket::opener_of(&typed).is_some()
+                    || bracket::is_quote(&typed);
+                if self.auto_pair && is_closer && next == Some(typed.as_str()) {
+                    // Type over the closer
+                    cursor.x += 1;
+                    return Ok(());
+                }
+                let prev = bracket::grapheme_before(buffer, *cursor);
+                let closer = match bracket::closer_of(&typed) {
+                    Some(closer) => Some(closer),
+                    // Don't pair e.g. the apostrophe in "don't"
+                    None if bracket::is_quote(&typed) => {
+                        let is_word = |g: &str| g.chars().all(char::is_alphanumeric);
+                        (!prev.is_some_and(is_word)).then_some(typed.as_str())
+                    }
+                    None => None,
+                };
+                let line = &buffer[cursor.y];
+                let before: String = line.graphemes().take(cursor.x as usize).collect();
+                let is_first_grapheme = before.trim().is_empty();
+                if is_first_grapheme && bracket::opener_of(&typed).is_some() {
+                    // Dedent the line when it starts with a closing bracket
+                    let dedented = self.indenter.as_mut()
+                        .and_then(|indenter| indenter.dedent(&before, &typed));
+                    if let Some(dedented) = dedented {
+                        buffer[cursor.y].set_indentation(&dedented);
+                        cursor.x = dedented.graphemes(true).count() as u16;
+                    }
+                }
+                if let (true, Some(closer)) = (self.auto_pair, closer) {
+                    let closer = closer.to_string();
+                    buffer.insert_str(*cursor, &(typed + &closer));
+                } else {
+                    buffer.insert_char(*cursor, c);
+                }
                 cursor.x += 1;
             }
             State::Navigate(NavigateState { preview, cursor, .. }) => {
@@ -821,7 +2077,7 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 matches,
                 current,
             }) => {
-                let prompt_len = self.reverse_search_prompt.len();
+                let prompt_len = self.reverse_search_prompt.width() as usize;
                 if regex.len() >= dims.width as usize - prompt_len - 1 {
                     return Ok(()); // NOP
                 }
@@ -845,13 +2101,46 @@ impl<'eval, W: Write> Repl<'eval, W> {
 
     /// Add a newline to the current cmd
     fn cmd_insert_newline(&mut self) -> ReplBlockResult<()> {
+        self.replace_region();
         match &mut self.state {
             State::Edit(EditState { buffer, cursor }) => {
-                buffer.insert_empty_line(*cursor);
-                *cursor = Coords {
-                    x: ORIGIN.x,
-                    y: cursor.y + 1
+                let Some(indenter) = &mut self.indenter else {
+                    buffer.insert_empty_line(*cursor);
+                    *cursor = Coords { x: ORIGIN.x, y: cursor.y + 1 };
+                    return Ok(());
                 };
+                let line = &buffer[cursor.y];
+                let prev_line: String = line.graphemes().take(cursor.x as usize).collect();
+                let rest: String = line.graphemes().skip(cursor.x as usize).collect();
+                let rest = rest.trim_start();
+                let indentation = indenter.indent(&prev_line);
+                let indentation_len = indentation.graphemes(true).count() as u16;
+                let first_of_rest = rest.graphemes(true).next();
+                let closes_block = first_of_rest.and_then(bracket::opener_of).is_some();
+                let opens_block = prev_line.trim_end().graphemes(true).next_back()
+                    .and_then(bracket::closer_of)
+                    .is_some_and(|closer| first_of_rest == Some(closer));
+                buffer.insert_empty_line(*cursor);
+                let new_y = cursor.y + 1;
+                if opens_block {
+                    // Move the closer to a line of its own, e.g. `{|}`
+                    // becomes a block containing an empty, indented line
+                    let outer = indent::leading_whitespace(&prev_line).to_string();
+                    buffer.insert_empty_line(Coords { x: 0, y: new_y });
+                    buffer[new_y].set_indentation(&indentation);
+                    buffer[new_y + 1].set_indentation(&outer);
+                } else if closes_block {
+                    let dedented = first_of_rest
+                        .and_then(|closer| indenter.dedent(&indentation, closer))
+                        .unwrap_or_else(|| indentation.clone());
+                    buffer[new_y].set_indentation(&dedented);
+                    let dedented_len = dedented.graphemes(true).count() as u16;
+                    *cursor = Coords { x: dedented_len, y: new_y };
+                    return Ok(());
+                } else {
+                    buffer[new_y].set_indentation(&indentation);
+                }
+                *cursor = Coords { x: indentation_len, y: new_y };
             }
             State::Navigate(NavigateState { preview, cursor, .. }) => {
                 self.state = State::Edit(EditState {
@@ -870,10 +2159,18 @@ impl<'eval, W: Write> Repl<'eval, W> {
     /// Delete the grapheme before the cursor in of the current cmd
     /// Do nothing if there if there is no grapheme before the cursor.
     fn cmd_rm_grapheme_before_cursor(&mut self) -> ReplBlockResult<()> {
+        if self.remove_region().is_some() {
+            return Ok(());
+        }
         match &mut self.state {
             State::Edit(EditState { buffer, cursor }) => {
                 if cursor.y == 0 && cursor.x == 0 {
                     // NOP
+                } else if cursor.x > 0 && self.auto_pair && is_empty_pair(buffer, *cursor) {
+                    // Remove both halves of an auto-paired bracket or quote
+                    buffer.rm_grapheme_at(*cursor);
+                    buffer.rm_grapheme_before(*cursor);
+                    cursor.x -= 1;
                 } else if cursor.y == 0 && cursor.x > 0 {
                     buffer.rm_grapheme_before(*cursor);
                     cursor.x -= 1;
@@ -904,9 +2201,9 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 matches,
                 current,
             }) => {
-                let prompt_len = self.reverse_search_prompt.len();
+                let prompt_len = self.reverse_search_prompt.width() as usize;
                 let rmidx = cursor.x as usize - prompt_len;
-                if regex.len() == 0 || rmidx == 0 {
+                if regex.is_empty() || rmidx == 0 {
                     return Ok(()); // NOP
                 }
                 let mut re: Vec<&str> = regex.graphemes(true).collect();
@@ -928,6 +2225,9 @@ impl<'eval, W: Write> Repl<'eval, W> {
     /// Delete the grapheme at the position of the cursor in of the current cmd.
     /// Do nothing if there if there is no grapheme at the cursor.
     fn cmd_rm_grapheme_at_cursor(&mut self) -> ReplBlockResult<()> {
+        if self.remove_region().is_some() {
+            return Ok(());
+        }
         match &mut self.state {
             State::Edit(EditState { buffer, cursor }) => {
                 let is_end_of_line = cursor.x == buffer[cursor.y].count_graphemes();
@@ -958,10 +2258,10 @@ impl<'eval, W: Write> Repl<'eval, W> {
                 matches,
                 current,
             }) => {
-                let prompt_len = self.reverse_search_prompt.len();
+                let prompt_len = self.reverse_search_prompt.width() as usize;
                 let rmidx = cursor.x as usize - prompt_len;
                 let is_end_of_regex_line = rmidx == regex.graphemes(true).count();
-                if regex.len() == 0 || is_end_of_regex_line {
+                if regex.is_empty() || is_end_of_regex_line {
                     return Ok(()); // NOP
                 }
                 let mut re: Vec<&str> = regex.graphemes(true).collect();
@@ -979,24 +2279,97 @@ impl<'eval, W: Write> Repl<'eval, W> {
         Ok(())
     }
 
+    /// Show or hide the debug overlay.
+    fn cmd_toggle_debug_overlay(&mut self) -> ReplBlockResult<()> {
+        self.debug_overlay = !self.debug_overlay;
+        Ok(())
+    }
+
+    /// Edit the current cmd in an external editor i.e. `$VISUAL` or `$EDITOR`,
+    /// and then load the edited cmd back into the input area.
+    fn cmd_edit_in_external_editor(&mut self) -> ReplBlockResult<()> {
+        self.mark = None;
+        // Keep empty lines, so that they survive the round trip
+        let text = match &self.state {
+            State::Edit(EditState { buffer, .. }) => buffer.text(),
+            State::Navigate(NavigateState { preview, .. }) => preview.text(),
+            State::Search(SearchState { preview, .. }) => preview.text(),
+        };
+        // Hand the terminal over to the editor
+        self.clear_input_area()?;
+        self.set_cursor_style(cursor::SetCursorStyle::DefaultUserShape)?;
+        self.set_bracketed_paste(false)?;
+        self.set_mouse_capture(false)?;
+        self.sink.flush()?;
+        terminal::disable_raw_mode()?;
+        let edited = editor::edit(&text);
+        self.set_cursor_style(cursor::SetCursorStyle::BlinkingBar)?;
+        self.set_bracketed_paste(true)?;
+        self.set_mouse_capture(true)?;
+        match edited {
+            Ok(Some(edited)) => {
+                let buffer = Cmd::from_source_code(&self.tabs.expand(&edited, 0));
+                self.state = State::Edit(EditState {
+                    cursor: buffer.end_of_cmd(),
+                    buffer,
+                });
+                if self.eval_after_external_edit {
+                    self.cmd_eval()?;
+                }
+            }
+            Ok(None) => {/* The edits were discarded; keep the current cmd */}
+            Err(err) => {
+                let msg = format!("Failed to launch the editor: {err}");
+                writeln!(self.sink, "{}", self.theme.error.apply(msg))?;
+            }
+        }
+        self.sink.flush()?;
+        terminal::enable_raw_mode()?;
+        Ok(())
+    }
+
     /// Execute the current cmd
     fn cmd_eval(&mut self) -> ReplBlockResult<()> {
+        self.mark = None;
         match &mut self.state {
-            State::Edit(EditState { buffer, cursor }) => {
+            State::Edit(EditState { buffer, .. }) => {
                 let source_code = buffer.to_source_code();
                 if source_code.is_empty() {
                     return Ok(());
                 }
+                let start = Instant::now();
+                let eval_mode = EvalMode::enter(self.quiet_eval)?;
+                self.screen.invalidate(); // The output is printed over the input area
                 { // Ensure output is written on a new line
                     writeln!(self.sink)?;
                     self.sink.flush()?;
                 }
-                let cmd = std::mem::take(buffer);
-                let _hidx = self.history.add_cmd(cmd);
-                self.history.write_to_file(&self.history_filepath)?;
-                (*self.evaluator)(source_code.as_str())?;
-                self.height = 1; // reset
-                *cursor = ORIGIN;
+                let action = match self.expand_history(&source_code)? {
+                    Some(expanded) => {
+                        let State::Edit(EditState { buffer, cursor }) = &mut self.state else {
+                            unreachable!("[Repl::cmd_eval] not in edit mode");
+                        };
+                        let mut cmd = std::mem::take(buffer);
+                        *cursor = ORIGIN;
+                        if expanded != source_code {
+                            cmd = Cmd::from_source_code(&expanded);
+                        }
+                        self.eval(cmd, expanded)?
+                    }
+                    None => EvalAction::None, // Keep the cmd, so that it can be corrected
+                };
+                self.last_duration = Some(start.elapsed());
+                self.refresh_prompts();
+                self.height = self.min_height(); // reset
+                self.scroll = 0;
+                self.apply_prompt_seed();
+                self.apply_eval_action(action)?;
+                for _ in 1..self.height { // Make room for the status line
+                    writeln!(self.sink)?;
+                }
+                self.sink.flush()?;
+                eval_mode.leave()?;
+                self.collect_typeahead()?;
             }
             State::Navigate(NavigateState { preview, cursor, .. }) => {
                 self.state = State::Edit(EditState {
@@ -1015,11 +2388,206 @@ impl<'eval, W: Write> Repl<'eval, W> {
         }
         Ok(())
     }
+
+    /// Collect the events for keys that were typed while a cmd was being
+    /// evaluated, so that they can be replayed into the next prompt.
+    fn collect_typeahead(&mut self) -> ReplBlockResult<()> {
+        while event::poll(Duration::ZERO)? {
+            self.typeahead.push_back(event::read()?);
+        }
+        Ok(())
+    }
+
+    /// Perform history expansion on `source_code`, if enabled, and echo the
+    /// expanded cmd.  Return `None` if expansion failed, in which case the
+    /// error is printed instead.
+    fn expand_history(&mut self, source_code: &str) -> ReplBlockResult<Option<String>> {
+        if !self.history_expansion {
+            return Ok(Some(source_code.to_string()));
+        }
+        match expansion::expand(&self.history, source_code) {
+            Ok(None) => Ok(Some(source_code.to_string())), // Nothing to expand
+            Ok(Some(expanded)) => {
+                writeln!(self.sink, "{expanded}")?; // echo
+                Ok(Some(expanded))
+            }
+            Err(err) => {
+                writeln!(self.sink, "{}", self.theme.error.apply(err))?;
+                self.sink.flush()?;
+                self.last_status = Some(Status::Failure);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Record and then evaluate `cmd`, the source code of which is
+    /// `source_code`, either as a meta-command or by using the evaluator.
+    /// Return the action that the evaluator asked for, if any.
+    fn eval(&mut self, cmd: Cmd, source_code: String) -> ReplBlockResult<EvalAction> {
+        let _hidx = self.history.add_cmd(cmd);
+        self.history.write_to_file(&self.history_filepath)?;
+        let meta_cmd = meta::parse(&self.meta_prefix, &source_code)
+            .filter(|_| self.meta_commands);
+        let is_meta = meta_cmd.is_some();
+        // Only log the length, as cmds can contain secrets
+        log::debug!("eval: {} bytes, meta-command: {is_meta}", source_code.len());
+        let start = SystemTime::now();
+        let capture = if self.transcript_filepath.is_some() || self.asciicast.is_some() {
+            // The output of meta-commands goes through the sink, which
+            // records it already
+            let tee = self.asciicast.clone().filter(|_| !is_meta).map(|asciicast| {
+                Box::new(move |bytes: &[u8]| {
+                    // Captured output is written outside of raw mode
+                    if let Err(err) = asciicast.record_output(bytes, true) {
+                        log::debug!("asciicast: failed to record output: {err}");
+                    }
+                }) as Tee
+            });
+            Some(StdoutCapture::start(tee)?)
+        } else {
+            None
+        };
+        let result = if let Some((name, args)) = meta_cmd {
+            self.eval_meta_cmd(name, args).map(|status| (status, EvalAction::None))
+        } else {
+            (*self.evaluator)(source_code.as_str()).map(|action| (Status::Success, action))
+        };
+        if let Some(capture) = capture {
+            self.sink.flush()?;
+            let output = capture.finish()?;
+            if let Some(path) = &self.transcript_filepath {
+                self.transcript.record(source_code, output, is_meta, start);
+                self.transcript.write_to_file(path)?;
+            }
+        }
+        let (status, action) = match result {
+            Ok((status, action)) => (status, action),
+            Err(err) => { // Report the error, rather than exiting the REPL
+                writeln!(self.sink, "{}", self.theme.error.apply(err.to_string()))?;
+                self.sink.flush()?;
+                (Status::Failure, EvalAction::None)
+            }
+        };
+        let status = match action { // The cmd was handed back for correction
+            EvalAction::Edit(_) => Status::Failure,
+            _ => status,
+        };
+        let action_name = match &action { // Without the text, like the cmd
+            EvalAction::None => "None",
+            EvalAction::Edit(_) => "Edit",
+            EvalAction::Insert(_) => "Insert",
+            EvalAction::ClearScreen => "ClearScreen",
+        };
+        log::debug!("eval: {status:?}, action: {action_name}");
+        self.cmd_count += 1;
+        self.last_status = Some(status);
+        Ok(action)
+    }
+
+    /// Evaluate the meta-command called `name` with arguments `args`.
+    fn eval_meta_cmd(&mut self, name: &str, args: &str) -> ReplBlockResult<Status> {
+        if let Some(mcmd) = self.meta_cmds.iter_mut().find(|mcmd| mcmd.name == name) {
+            (*mcmd.handler)(args)?;
+            return Ok(Status::Success);
+        }
+        let prefix = &self.meta_prefix;
+        let Some(builtin) = Builtin::from_name(name) else {
+            let msg = format!(
+                "Unknown meta-command: {prefix}{name} (try {prefix}{})",
+                Builtin::Help.name(),
+            );
+            writeln!(self.sink, "{}", self.theme.error.apply(msg))?;
+            self.sink.flush()?;
+            return Ok(Status::Failure);
+        };
+        let mut status = Status::Success;
+        match builtin {
+            Builtin::Help => {
+                let usages: Vec<(String, &str)> = Builtin::ALL.iter()
+                    .filter(|b| !self.meta_cmds.iter().any(|mcmd| mcmd.name == b.name()))
+                    .map(|b| (format!("{prefix}{} {}", b.name(), b.usage()), b.help()))
+                    .chain(self.meta_cmds.iter().map(|mcmd| {
+                        (format!("{prefix}{}", mcmd.name), mcmd.help.as_str())
+                    }))
+                    .collect();
+                let width = usages.iter()
+                    .map(|(usage, _)| usage.graphemes(true).count())
+                    .max()
+                    .unwrap_or(0);
+                writeln!(self.sink, "Meta-commands:")?;
+                for (usage, help) in usages {
+                    writeln!(self.sink, "  {usage:width$}  {help}")?;
+                }
+            }
+            Builtin::History if args.is_empty() => {
+                write!(self.sink, "{}", self.history)?;
+            }
+            Builtin::History => {
+                write!(self.sink, "{}", self.history.filtered(args))?;
+            }
+            Builtin::Clear => self.clear_screen()?,
+            Builtin::Save if args.is_empty() => {
+                let msg = format!("Usage: {prefix}{} {}", builtin.name(), builtin.usage());
+                writeln!(self.sink, "{}", self.theme.error.apply(msg))?;
+                status = Status::Failure;
+            }
+            Builtin::Save => {
+                let session: String = self.history.iter()
+                    .skip(self.session_start)
+                    .map(|(_, cmd)| cmd.to_source_code() + "\n")
+                    .collect();
+                match std::fs::write(args, session) {
+                    Ok(()) => writeln!(self.sink, "Saved session to {args}")?,
+                    Err(err) => {
+                        let msg = format!("Failed to save session to {args}: {err}");
+                        writeln!(self.sink, "{}", self.theme.error.apply(msg))?;
+                        status = Status::Failure;
+                    }
+                }
+            }
+            Builtin::Keys => {
+                let width = Self::KEY_BINDINGS.iter()
+                    .map(|(keys, _)| keys.graphemes(true).count())
+                    .max()
+                    .unwrap_or(0);
+                let custom: Vec<(String, &str)> = self.key_bindings.iter()
+                    .map(|kb| (binding::describe(&kb.key), kb.description.as_str()))
+                    .collect();
+                let width = custom.iter()
+                    .map(|(keys, _)| keys.graphemes(true).count())
+                    .fold(width, std::cmp::max);
+                writeln!(self.sink, "Key bindings:")?;
+                let builtin = Self::KEY_BINDINGS.iter().map(|&(keys, desc)| (keys, desc));
+                let custom = custom.iter().map(|(keys, desc)| (keys.as_str(), *desc));
+                for (keys, description) in custom.chain(builtin) {
+                    writeln!(self.sink, "  {keys:width$}  {description}")?;
+                }
+            }
+        }
+        self.sink.flush()?;
+        Ok(status)
+    }
 }
 
 #[derive(Clone, Copy,  Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Dims { pub width: u16, pub height: u16 }
 
+/// What's known about the layout of the input area while rendering it,
+/// for logging and the debug overlay.
+#[derive(Clone, Copy, Debug)]
+struct RenderInfo {
+    cursor: Coords,
+    uncursor: Coords,
+    scroll: u16,
+    /// The number of rows available for the cmd
+    rows: u16,
+    num_unlines: u16,
+    term_width: u16,
+    term_height: u16,
+}
+
+/// A position, either in a `Cmd` or on screen.  In a `Cmd`, `y` is the index
+/// of a `Line` and `x` is the index of a grapheme within that `Line`.
 #[derive(Clone, Copy,  Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Coords { pub x: u16, pub y: u16 }
 
@@ -1032,6 +2600,73 @@ impl std::fmt::Display for Coords {
 }
 
 
+/// A range of graphemes in a (compressed) cmd that's rendered in a
+/// different style than the rest of the cmd, e.g. a search match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Highlight {
+    /// The position of the first highlighted grapheme
+    start: Coords,
+    /// The position just after the last highlighted grapheme
+    end: Coords,
+    style: ContentStyle,
+}
+
+impl Highlight {
+    fn contains(&self, pos: Coords) -> bool {
+        let key = |c: Coords| (c.y, c.x);
+        key(self.start) <= key(pos) && key(pos) < key(self.end)
+    }
+}
+
+/// Return whether `cursor` is located between an opening bracket or quote
+/// and its closer, e.g. `(|)`.
+fn is_empty_pair(cmd: &Cmd, cursor: Coords) -> bool {
+    let prev = bracket::grapheme_before(cmd, cursor);
+    let next = bracket::grapheme_at(cmd, cursor);
+    match (prev, next) {
+        (Some(prev), Some(next)) if bracket::is_quote(prev) => prev == next,
+        (Some(prev), Some(next)) => bracket::closer_of(prev) == Some(next),
+        _ => false,
+    }
+}
+
+/// Highlight the bracket near `cursor` in `cmd`, and the one matching it.
+fn bracket_highlights(cmd: &Cmd, cursor: Coords, style: ThemeStyle) -> Vec<Highlight> {
+    let Some((near, far)) = bracket::find_matching_pair(cmd, cursor) else {
+        return vec![];
+    };
+    let style = style.to_content_style();
+    [near, far].into_iter()
+        .map(|pos| Highlight {
+            start: pos,
+            end: Coords { x: pos.x + 1, y: pos.y },
+            style,
+        })
+        .collect()
+}
+
+/// Highlight the parts of each `Line` of `cmd` that match `regex`.
+fn search_highlights(cmd: &Cmd, regex: &str, style: ThemeStyle) -> Vec<Highlight> {
+    let Ok(regex) = Regex::new(regex) else { return vec![] };
+    let style = style.to_content_style();
+    cmd.lines().iter()
+        .enumerate()
+        .flat_map(|(y, line)| {
+            let y = y as u16;
+            let text = line.to_string();
+            regex.find_iter(&text)
+                .filter(|m| !m.is_empty())
+                .map(|m| Highlight {
+                    start: Coords { x: line.grapheme_idx_of(m.start()), y },
+                    end: Coords { x: line.grapheme_idx_of(m.end()), y },
+                    style,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+
 #[derive(Debug)]
 enum State {
     Edit(EditState),
@@ -1039,6 +2674,53 @@ enum State {
     Search(SearchState),
 }
 
+/// The built-in actions that keys are bound to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Action {
+    Nop,
+    StartCtrlXChord,
+    EditInExternalEditor,
+    ToggleDebugOverlay,
+    ExitRepl,
+    Suspend,
+    CancelNav,
+    Eval,
+    NavUp,
+    NavDown,
+    NavLeft,
+    NavRight,
+    NavToStartOfCmd,
+    NavToEndOfCmd,
+    AcceptHintWord,
+    ReverseSearchHistory,
+    NavHistoryUp,
+    NavHistoryDown,
+    SetMark,
+    SelectLeft,
+    SelectRight,
+    SelectUp,
+    SelectDown,
+    SelectToStartOfCmd,
+    SelectToEndOfCmd,
+    CutRegion,
+    CopyRegion,
+    Yank,
+    InsertNewline,
+    InsertTab,
+    RmGraphemeBeforeCursor,
+    RmGraphemeAtCursor,
+}
+
+impl State {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Edit(_) => "Edit",
+            Self::Navigate(_) => "Navigate",
+            Self::Search(_) => "Search",
+        }
+    }
+}
+
 /// Editing a `Cmd`
 #[derive(Debug)]
 struct EditState {
@@ -1077,3 +2759,227 @@ struct SearchState {
     /// The current entry in `self.matches`
     current: usize,
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use itertools::Itertools;
+    use std::collections::HashSet;
+
+    type TestRepl<'eval> = Repl<'eval, Stdout>;
+
+    /// Keys that are described in `TestRepl::KEY_BINDINGS`, or that could be.
+    fn candidate_keys() -> Vec<KeyEvent> {
+        let codes = ('a'..='z').chain([' ']).map(KeyCode::Char).chain([
+            KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace, KeyCode::Delete,
+            KeyCode::Esc, KeyCode::Left, KeyCode::Right, KeyCode::Up,
+            KeyCode::Down, KeyCode::Home, KeyCode::End, KeyCode::PageUp,
+            KeyCode::PageDown,
+        ]);
+        codes
+            .cartesian_product([
+                KeyModifiers::NONE,
+                KeyModifiers::CONTROL,
+                KeyModifiers::ALT,
+                KeyModifiers::SHIFT,
+            ])
+            .map(|(code, modifiers)| KeyEvent::new(code, modifiers))
+            .collect()
+    }
+
+    /// Return the path of a temporary file called `name`, with `extension`.
+    fn temp_filepath(name: &str, extension: &str) -> ReplBlockResult<Utf8PathBuf> {
+        let filepath = std::env::temp_dir()
+            .join(format!("repl-block-{name}-{}.{extension}", std::process::id()));
+        Ok(Utf8PathBuf::try_from(filepath)?)
+    }
+
+    /// Build a `Repl` that renders to memory, and keeps its history in a
+    /// temporary file called `name`.
+    fn test_repl<'eval>(name: &str) -> ReplBlockResult<Repl<'eval, Vec<u8>>> {
+        ReplBuilder::default()
+            .sink(vec![])
+            .history_filepath(temp_filepath(name, "history")?)
+            .build()
+    }
+
+    #[test]
+    fn build_without_a_tty() -> ReplBlockResult<()> {
+        let repl = test_repl("build")?;
+        assert!(!terminal::is_raw_mode_enabled()?);
+        assert!(!repl.sink.get_ref().is_empty());
+        let _ = std::fs::remove_file(&repl.history_filepath);
+        Ok(())
+    }
+
+    #[test]
+    fn documented_key_bindings_are_bound() {
+        let candidates = candidate_keys();
+        let parse = |description: &str| candidates.iter()
+            .find(|key| binding::describe(key) == description)
+            .copied()
+            .unwrap_or_else(|| panic!("unknown key {description:?}"));
+        for &(keys, _) in TestRepl::KEY_BINDINGS {
+            if keys == "Shift-<movement>" {
+                continue;
+            }
+            for chord in keys.split(", ") {
+                let mut ctrl_x = false;
+                for key in chord.split(' ').map(parse) {
+                    let action = TestRepl::key_action(&key, ctrl_x);
+                    assert!(action.is_some(), "{chord:?} is not bound");
+                    ctrl_x = action == Some(Action::StartCtrlXChord);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bound_keys_are_documented() {
+        let documented: HashSet<&str> = TestRepl::KEY_BINDINGS.iter()
+            .flat_map(|&(keys, _)| keys.split(", "))
+            .collect();
+        let is_selection = |action| matches!(action,
+            Action::SelectLeft | Action::SelectRight | Action::SelectUp
+            | Action::SelectDown | Action::SelectToStartOfCmd | Action::SelectToEndOfCmd
+        );
+        for key in candidate_keys() {
+            let description = binding::describe(&key);
+            match TestRepl::key_action(&key, false) {
+                None | Some(Action::Nop) => {}
+                Some(Action::StartCtrlXChord) => assert!(
+                    documented.iter().any(|keys| keys.starts_with(&format!("{description} "))),
+                    "no {description} chord is documented"
+                ),
+                Some(action) if is_selection(action) => {
+                    assert!(documented.contains("Shift-<movement>"));
+                }
+                Some(_) => assert!(
+                    documented.contains(description.as_str()),
+                    "{description} is not documented"
+                ),
+            }
+            if TestRepl::key_action(&key, true).is_some() {
+                let chord = format!("Ctrl-X {description}");
+                assert!(documented.contains(chord.as_str()), "{chord} is not documented");
+            }
+        }
+    }
+
+    /// Evaluate `src` in `repl`, and apply the resulting action.
+    fn eval_and_apply(repl: &mut Repl<'_, Vec<u8>>, src: &str) -> ReplBlockResult<()> {
+        let action = repl.eval(Cmd::from_source_code(src), src.to_string())?;
+        repl.apply_eval_action(action)
+    }
+
+    #[test]
+    fn eval_actions() -> ReplBlockResult<()> {
+        let mut repl = test_repl("eval-actions")?;
+        repl.evaluator = Box::new(|src: &str| match src {
+            "edit" => Ok(EvalAction::Edit("fixed".to_string())),
+            "insert" => Ok(EvalAction::Insert("inserted".to_string())),
+            "clear" => Ok(EvalAction::ClearScreen),
+            "fail" => Err(std::io::Error::other("rejected").into()),
+            _ => Ok(EvalAction::None),
+        });
+        let edited = |repl: &Repl<'_, Vec<u8>>| match &repl.state {
+            State::Edit(EditState { buffer, cursor }) => (buffer.to_source_code(), *cursor),
+            _ => panic!("not in edit mode"),
+        };
+
+        eval_and_apply(&mut repl, "ok")?;
+        assert_eq!(repl.last_status, Some(Status::Success));
+        assert_eq!(edited(&repl), (String::new(), ORIGIN));
+
+        eval_and_apply(&mut repl, "edit")?;
+        assert_eq!(repl.last_status, Some(Status::Failure));
+        assert_eq!(edited(&repl), ("fixed".to_string(), Coords { x: 5, y: 0 }));
+
+        eval_and_apply(&mut repl, "insert")?;
+        assert_eq!(repl.last_status, Some(Status::Success));
+        assert_eq!(edited(&repl), ("fixedinserted".to_string(), Coords { x: 13, y: 0 }));
+
+        repl.sink.get_mut().clear();
+        eval_and_apply(&mut repl, "clear")?;
+        assert_eq!(repl.last_status, Some(Status::Success));
+        assert!(repl.sink.get_ref().ends_with(b"\x1b[2J\x1b[3J\x1b[1;1H"));
+
+        repl.sink.get_mut().clear();
+        eval_and_apply(&mut repl, "fail")?;
+        assert_eq!(repl.last_status, Some(Status::Failure));
+        assert!(String::from_utf8_lossy(repl.sink.get_ref()).contains("rejected"));
+        assert_eq!(edited(&repl), ("fixedinserted".to_string(), Coords { x: 13, y: 0 }));
+
+        assert_eq!(repl.cmd_count, 5);
+        let _ = std::fs::remove_file(&repl.history_filepath);
+        Ok(())
+    }
+
+    #[test]
+    fn clear_meta_command_moves_the_cursor_home() -> ReplBlockResult<()> {
+        let mut repl = test_repl("clear")?;
+        repl.meta_commands = true;
+        repl.sink.get_mut().clear();
+        eval_and_apply(&mut repl, ":clear")?;
+        assert_eq!(repl.last_status, Some(Status::Success));
+        assert!(repl.sink.get_ref().ends_with(b"\x1b[2J\x1b[3J\x1b[1;1H"));
+        let _ = std::fs::remove_file(&repl.history_filepath);
+        Ok(())
+    }
+
+    #[test]
+    fn asciicast_records_a_sink_that_is_set_later() -> ReplBlockResult<()> {
+        let cast_filepath = temp_filepath("later-sink", "cast")?;
+        let repl = ReplBuilder::default()
+            .asciicast(Asciicast::create(&cast_filepath)?)
+            .sink(vec![])
+            .history_filepath(temp_filepath("later-sink", "history")?)
+            .build()?;
+        let contents = std::fs::read_to_string(&cast_filepath)?;
+        let _ = std::fs::remove_file(&cast_filepath);
+        let _ = std::fs::remove_file(&repl.history_filepath);
+        let output: String = contents.lines()
+            .skip(1) // The header
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .filter(|event| event[1] == "o")
+            .filter_map(|event| event[2].as_str().map(str::to_string))
+            .collect();
+        // Outside of raw mode, `\n` is recorded as `\r\n`
+        assert_eq!(output.replace("\r\n", "\n").as_bytes(), repl.sink.get_ref().as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn scroll_during_an_empty_search() -> ReplBlockResult<()> {
+        let mut repl = test_repl("empty-search")?;
+        repl.cmd_reverse_search_history()?;
+        let State::Search(SearchState { matches, .. }) = &repl.state else {
+            panic!("not in search mode");
+        };
+        assert!(matches.is_empty());
+        for kind in [MouseEventKind::ScrollUp, MouseEventKind::ScrollDown] {
+            let event = MouseEvent { kind, column: 0, row: 0, modifiers: KeyModifiers::NONE };
+            repl.cmd_mouse(event)?;
+        }
+        let State::Search(SearchState { preview, current, .. }) = &repl.state else {
+            panic!("not in search mode");
+        };
+        assert_eq!((preview, *current), (&Cmd::default(), 0));
+        let _ = std::fs::remove_file(&repl.history_filepath);
+        Ok(())
+    }
+
+    #[test]
+    fn events_are_logged_without_text() {
+        let key = |code, modifiers| Event::Key(KeyEvent::new(code, modifiers));
+        let describe = TestRepl::describe_event;
+        assert_eq!(describe(&key(KeyCode::Char('s'), KeyModifiers::NONE)), "Key(Char, Press)");
+        assert_eq!(describe(&key(KeyCode::Char('S'), KeyModifiers::SHIFT)), "Key(Char, Press)");
+        assert_eq!(describe(&key(KeyCode::Char('r'), KeyModifiers::CONTROL)), "Key(Ctrl-R, Press)");
+        assert_eq!(describe(&Event::Paste("secret".to_string())), "Paste(6 bytes)");
+        assert_eq!(describe(&Event::Resize(80, 24)), "Resize(80, 24)");
+    }
+}
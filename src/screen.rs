@@ -0,0 +1,291 @@
+//! Differential rendering of the input area: the UI is first drawn into a
+//! `Frame`, which is then compared to the previously drawn `Frame` so that
+//! only the cells that changed are written to the terminal.
+
+use crate::repl::Coords;
+use crossterm::{cursor, queue, style, terminal};
+use crossterm::style::{ContentStyle, StyledContent};
+use itertools::Itertools;
+use std::io::Write;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// A single cell of the terminal, holding one grapheme.  A wide grapheme
+/// e.g. a CJK character occupies its own cell and the cell after it, which
+/// holds an empty grapheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Cell {
+    grapheme: String,
+    style: ContentStyle,
+}
+
+impl Cell {
+    fn blank() -> Self {
+        Self { grapheme: " ".to_string(), style: ContentStyle::default() }
+    }
+
+    /// The cell that's covered by the wide grapheme in the cell before it
+    fn continuation(style: ContentStyle) -> Self {
+        Self { grapheme: String::new(), style }
+    }
+
+    fn is_continuation(&self) -> bool {
+        self.grapheme.is_empty()
+    }
+}
+
+/// Return the number of cells that `grapheme` occupies on the terminal.
+pub(crate) fn grapheme_width(grapheme: &str) -> u16 {
+    std::cmp::max(1, grapheme.width()) as u16
+}
+
+/// The contents of the input area, with coordinates relative to its
+/// top-left corner.  Text is drawn at the position of a pen, much like
+/// text is printed at the position of the terminal cursor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    width: u16,
+    /// Each line only holds cells up to the last one that was drawn
+    lines: Vec<Vec<Cell>>,
+    pen: Coords,
+    cursor: Coords,
+}
+
+impl Frame {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            lines: vec![vec![]; height as usize],
+            pen: Coords { x: 0, y: 0 },
+            cursor: Coords { x: 0, y: 0 },
+        }
+    }
+
+    pub(crate) fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> u16 {
+        self.lines.len() as u16
+    }
+
+    pub(crate) fn move_to(&mut self, x: u16, y: u16) {
+        self.pen = Coords { x, y };
+    }
+
+    pub(crate) fn move_to_column(&mut self, x: u16) {
+        self.pen.x = x;
+    }
+
+    /// Move the pen to the start of the next line.
+    pub fn next_line(&mut self) {
+        self.pen = Coords { x: 0, y: self.pen.y + 1 };
+    }
+
+    /// Draw `text` in the default style at the pen, and advance the pen.
+    pub fn print(&mut self, text: impl std::fmt::Display) {
+        self.print_with(&text.to_string(), ContentStyle::default());
+    }
+
+    /// Draw styled `content` at the pen, and advance the pen.
+    pub fn print_styled<D: std::fmt::Display>(&mut self, content: &StyledContent<D>) {
+        self.print_with(&content.content().to_string(), *content.style());
+    }
+
+    /// Draw `text` in `style` at the pen, and advance the pen.  Wide
+    /// graphemes occupy 2 cells, and others a single cell.
+    /// Text that doesn't fit is cut off.
+    fn print_with(&mut self, text: &str, style: ContentStyle) {
+        let Some(line) = self.lines.get_mut(self.pen.y as usize) else {
+            return; // Below the input area
+        };
+        for grapheme in text.graphemes(true) {
+            let width = grapheme_width(grapheme);
+            if self.pen.x + width > self.width {
+                return;
+            }
+            let (start, end) = (self.pen.x as usize, (self.pen.x + width) as usize);
+            if line.len() < end {
+                line.resize(end, Cell::blank());
+            }
+            // Blank out what remains of wide graphemes that are overwritten
+            if line[start].is_continuation() && start > 0 {
+                line[start - 1] = Cell::blank();
+            }
+            if line.get(end).is_some_and(Cell::is_continuation) {
+                line[end] = Cell::blank();
+            }
+            line[start] = Cell { grapheme: grapheme.to_string(), style };
+            for cell in &mut line[start + 1..end] {
+                *cell = Cell::continuation(style);
+            }
+            self.pen.x += width;
+        }
+    }
+
+    /// Set the position at which the terminal cursor is shown.
+    pub fn set_cursor(&mut self, x: u16, y: u16) {
+        self.cursor = Coords { x, y };
+    }
+}
+
+/// Keeps track of what's currently displayed in the input area, so that
+/// redrawing it only writes what changed.
+#[derive(Clone, Debug, Default)]
+pub struct Screen {
+    /// The last `Frame` that was drawn, unless the input area could have
+    /// been overwritten since, in which case the whole frame is redrawn
+    prev: Option<Frame>,
+    /// The height of the terminal when `prev` was drawn
+    term_height: u16,
+}
+
+impl Screen {
+    /// Forget what's displayed, e.g. after output was printed over the
+    /// input area, so that the next `Frame` is drawn in full.
+    pub(crate) fn invalidate(&mut self) {
+        self.prev = None;
+    }
+
+    /// Draw `frame` at the bottom of a terminal that's `term_height` lines
+    /// tall.  Lines that were added to the input area since the previous
+    /// frame are assumed to have been scrolled into view, and to be blank.
+    pub fn draw(
+        &mut self,
+        frame: Frame,
+        term_height: u16,
+        sink: &mut impl Write,
+    ) -> std::io::Result<()> {
+        let prev = self.prev.take().filter(|prev| {
+            prev.width == frame.width
+                && prev.height() <= frame.height()
+                && self.term_height == term_height
+        });
+        let origin_y = term_height.saturating_sub(frame.height());
+        for (y, line) in frame.lines.iter().enumerate() {
+            let row = origin_y + y as u16;
+            let Some(prev) = &prev else {
+                queue!(sink, cursor::MoveTo(0, row))?;
+                print_cells(line, sink)?;
+                queue!(sink, terminal::Clear(terminal::ClearType::UntilNewLine))?;
+                continue;
+            };
+            let old = prev.lines.get(y).map(Vec::as_slice).unwrap_or(&[]);
+            let prefix = line.iter().zip(old).take_while(|(new, old)| new == old).count();
+            let suffix = if line.len() == old.len() {
+                line[prefix..].iter().rev()
+                    .zip(old[prefix..].iter().rev())
+                    .take_while(|(new, old)| new == old)
+                    .count()
+            } else {
+                0
+            };
+            let changed = &line[prefix..line.len() - suffix];
+            if !changed.is_empty() {
+                queue!(sink, cursor::MoveTo(prefix as u16, row))?;
+                print_cells(changed, sink)?;
+            }
+            if line.len() < old.len() {
+                queue!(sink, cursor::MoveTo(line.len() as u16, row))?;
+                queue!(sink, terminal::Clear(terminal::ClearType::UntilNewLine))?;
+            }
+        }
+        queue!(sink, cursor::MoveTo(frame.cursor.x, origin_y + frame.cursor.y))?;
+        self.prev = Some(frame);
+        self.term_height = term_height;
+        Ok(())
+    }
+}
+
+/// Print `cells`, grouped into runs of cells that share the same style.
+fn print_cells(cells: &[Cell], sink: &mut impl Write) -> std::io::Result<()> {
+    for (style, run) in &cells.iter().chunk_by(|cell| cell.style) {
+        let text: String = run.map(|cell| cell.grapheme.as_str()).collect();
+        if style == ContentStyle::default() {
+            queue!(sink, style::Print(text))?;
+        } else {
+            queue!(sink, style::PrintStyledContent(style.apply(text)))?;
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn redraw_only_changes() {
+        let mut screen = Screen::default();
+        let mut frame = Frame::new(10, 2);
+        frame.print("abc");
+        frame.set_cursor(3, 0);
+        screen.draw(frame.clone(), 2, &mut vec![]).unwrap();
+
+        // An identical frame only moves the cursor
+        let mut sink = vec![];
+        screen.draw(frame.clone(), 2, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[1;4H");
+
+        // Only the changed cell is written
+        frame.move_to(1, 0);
+        frame.print("X");
+        let mut sink = vec![];
+        screen.draw(frame.clone(), 2, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[1;2HX\x1b[1;4H");
+
+        // Cells that are no longer drawn are cleared
+        let mut shorter = Frame::new(10, 2);
+        shorter.print("aX");
+        shorter.set_cursor(2, 0);
+        let mut sink = vec![];
+        screen.draw(shorter.clone(), 2, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[1;3H\x1b[K\x1b[1;3H");
+
+        // After invalidation, every line is drawn in full
+        screen.invalidate();
+        let mut sink = vec![];
+        screen.draw(shorter, 2, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[1;1HaX\x1b[K\x1b[2;1H\x1b[K\x1b[1;3H");
+    }
+
+    #[test]
+    fn wide_graphemes_occupy_2_cells() {
+        let mut screen = Screen::default();
+        let mut frame = Frame::new(5, 1);
+        frame.print("a你b好");
+        assert_eq!(frame.pen.x, 4); // `好` doesn't fit anymore
+        frame.set_cursor(4, 0);
+        let mut sink = vec![];
+        screen.draw(frame.clone(), 1, &mut sink).unwrap();
+        assert_eq!(sink, "\x1b[1;1Ha你b\x1b[K\x1b[1;5H".as_bytes());
+
+        // Overwriting half of `你` blanks out the other half
+        frame.move_to(2, 0);
+        frame.print("x");
+        let mut sink = vec![];
+        screen.draw(frame, 1, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[1;2H x\x1b[1;5H");
+    }
+
+    #[test]
+    fn diffed_keystroke_writes_only_the_key() {
+        let mut screen = Screen::default();
+        let mut frame = Frame::new(10, 3);
+        for line in ["one", "two", "six"] {
+            frame.print(line);
+            frame.next_line();
+        }
+        frame.set_cursor(3, 1);
+        screen.draw(frame.clone(), 3, &mut vec![]).unwrap();
+
+        // Typing at the end of the middle line writes only that key
+        frame.move_to(3, 1);
+        frame.print("x");
+        frame.set_cursor(4, 1);
+        let mut sink = vec![];
+        screen.draw(frame, 3, &mut sink).unwrap();
+        assert_eq!(sink, b"\x1b[2;4Hx\x1b[2;5H");
+    }
+}
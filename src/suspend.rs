@@ -0,0 +1,142 @@
+//! Job control i.e. suspending the process with `Ctrl-Z` and resuming it,
+//! which is only supported on Unix.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Flags that are set by signal handlers, so that the signals can be handled
+/// by the `Repl` outside of signal handler context.
+#[derive(Debug, Default)]
+pub(crate) struct Signals {
+    /// Whether the `Repl` is waiting for terminal events.  While it isn't,
+    /// SIGTSTP stops the process immediately, as it would by default.
+    reading: Arc<AtomicBool>,
+    /// Set when SIGTSTP was received while reading terminal events
+    suspend_requested: Arc<AtomicBool>,
+    /// Set when SIGCONT was received
+    continued: Arc<AtomicBool>,
+    /// The registered handlers, which are unregistered on drop
+    #[cfg(unix)]
+    ids: Vec<signal_hook::SigId>,
+}
+
+impl Signals {
+    /// Register handlers for SIGTSTP and SIGCONT.
+    #[cfg(unix)]
+    pub(crate) fn register() -> std::io::Result<Self> {
+        use signal_hook::consts::{SIGCONT, SIGTSTP};
+        let mut signals = Self::default();
+        let reading = Arc::clone(&signals.reading);
+        let suspend_requested = Arc::clone(&signals.suspend_requested);
+        let handler = move || {
+            if reading.load(Ordering::SeqCst) {
+                suspend_requested.store(true, Ordering::SeqCst);
+            } else {
+                // Only async-signal-safe operations are allowed here
+                let _ = signal_hook::low_level::emulate_default_handler(SIGTSTP);
+            }
+        };
+        // SAFETY: The handler only performs atomic stores and calls
+        //         `emulate_default_handler()`, which is async-signal-safe.
+        let id = unsafe { signal_hook::low_level::register(SIGTSTP, handler)? };
+        signals.ids.push(id);
+        let id = signal_hook::flag::register(SIGCONT, Arc::clone(&signals.continued))?;
+        signals.ids.push(id);
+        Ok(signals)
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn register() -> std::io::Result<Self> {
+        Ok(Self::default())
+    }
+
+    pub(crate) fn set_reading(&self, reading: bool) {
+        self.reading.store(reading, Ordering::SeqCst);
+    }
+
+    /// Return whether SIGTSTP was received, and reset the flag.
+    pub(crate) fn take_suspend_request(&self) -> bool {
+        self.suspend_requested.swap(false, Ordering::SeqCst)
+    }
+
+    /// Return whether SIGCONT was received, and reset the flag.
+    pub(crate) fn take_continued(&self) -> bool {
+        self.continued.swap(false, Ordering::SeqCst)
+    }
+
+    /// Return which signal, if any, should wake the `Repl` up from waiting
+    /// for terminal events, and reset its flag.  A suspend request takes
+    /// precedence, as suspending resumes afterwards anyway.
+    pub(crate) fn take_wakeup(&self) -> Option<Wakeup> {
+        if self.take_suspend_request() {
+            Some(Wakeup::Suspend)
+        } else if self.take_continued() {
+            Some(Wakeup::Continued)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why the `Repl` stopped waiting for terminal events.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Wakeup {
+    /// SIGTSTP was received e.g. because `Ctrl-Z` was pressed
+    Suspend,
+    /// SIGCONT was received after the process was stopped by other means,
+    /// e.g. with SIGSTOP, or with `Ctrl-Z` while a cmd was being evaluated
+    Continued,
+}
+
+impl Drop for Signals {
+    /// Unregister the handlers, so that they don't outlive the `Repl`.
+    /// NOTE: signal-hook doesn't reinstate the default disposition of a
+    ///       signal once its last handler is unregistered.
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for id in self.ids.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+/// Stop the process as if SIGTSTP was handled by its default handler.
+/// This returns once the process is continued with SIGCONT.
+#[cfg(unix)]
+pub(crate) fn suspend() -> std::io::Result<()> {
+    signal_hook::low_level::emulate_default_handler(signal_hook::consts::SIGTSTP)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn suspend() -> std::io::Result<()> {
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn wakeups() -> std::io::Result<()> {
+        use signal_hook::consts::SIGCONT;
+        let signals = Signals::register()?;
+        assert_eq!(signals.take_wakeup(), None);
+
+        // Raising SIGTSTP would stop the test process if another `Signals`
+        // isn't reading, so act as its handler does while reading
+        signals.set_reading(true);
+        signals.suspend_requested.store(true, Ordering::SeqCst);
+        signal_hook::low_level::raise(SIGCONT)?;
+        assert_eq!(signals.take_wakeup(), Some(Wakeup::Suspend));
+        assert_eq!(signals.take_wakeup(), Some(Wakeup::Continued));
+        assert_eq!(signals.take_wakeup(), None);
+
+        signal_hook::low_level::raise(SIGCONT)?;
+        assert_eq!(signals.take_wakeup(), Some(Wakeup::Continued));
+        assert_eq!(signals.take_wakeup(), None);
+        Ok(())
+    }
+}
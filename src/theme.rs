@@ -0,0 +1,327 @@
+//! Themes, which determine the styles used by the various parts of a `Repl`.
+
+use camino::Utf8Path;
+use crate::{
+    capability::TermCapabilities,
+    error::{ReplBlockError, ReplBlockResult},
+    prompt::StyledText,
+};
+use crossterm::style::{Attribute, Attributes, Color, ContentStyle, StyledContent};
+
+/// A set of styles for the various parts of a `Repl`.
+/// Themes can be loaded from TOML or JSON files, in which any style that
+/// isn't specified falls back to the one of `Theme::classic()`, e.g.
+///
+/// ```toml
+/// prompt = { fg = "green", bold = true }
+/// search_match = { fg = "#002b36", bg = "yellow" }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Theme {
+    /// The marker at the start of the default prompt
+    pub prompt_marker: Style,
+    /// The default prompt
+    pub prompt: Style,
+    /// The prompt used for command continuations
+    pub continue_prompt: Style,
+    /// The prompt used for reverse history search
+    pub search_prompt: Style,
+    /// The parts of a history entry that match the reverse search regex
+    pub search_match: Style,
+    /// Inline hints
+    pub hint: Style,
+    /// The bracket near the cursor and the bracket matching it
+    pub matching_bracket: Style,
+    /// Error messages
+    pub error: Style,
+    /// Selected text
+    pub selection: Style,
+    /// Emphasized parts of messages e.g. key names
+    pub accent: Style,
+    /// Indicators e.g. for content that is scrolled out of view
+    pub indicator: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::classic()
+    }
+}
+
+impl Theme {
+    /// The classic theme of `repl-block`.
+    pub fn classic() -> Self {
+        Self {
+            prompt_marker: Style::new().fg(Color::Yellow),
+            prompt: Style::new().fg(Color::Green).bold(),
+            continue_prompt: Style::new().fg(Color::Yellow),
+            search_prompt: Style::new().fg(Color::Yellow).italic(),
+            search_match: Style::new().fg(Color::Black).bg(Color::Yellow),
+            hint: Style::new().dim(),
+            matching_bracket: Style::new().fg(Color::Cyan).bold(),
+            error: Style::new().fg(Color::Red),
+            selection: Style::new().reverse(),
+            accent: Style::new().fg(Color::Magenta),
+            indicator: Style::new().dim(),
+        }
+    }
+
+    /// A theme in shades of blue and cyan.
+    pub fn ocean() -> Self {
+        Self {
+            prompt_marker: Style::new().fg(Color::DarkCyan),
+            prompt: Style::new().fg(Color::Cyan).bold(),
+            continue_prompt: Style::new().fg(Color::DarkCyan),
+            search_prompt: Style::new().fg(Color::Blue).italic(),
+            search_match: Style::new().fg(Color::Black).bg(Color::Cyan),
+            hint: Style::new().fg(Color::DarkGrey),
+            matching_bracket: Style::new().fg(Color::Yellow).bold(),
+            error: Style::new().fg(Color::Red).bold(),
+            selection: Style::new().fg(Color::Black).bg(Color::Blue),
+            accent: Style::new().fg(Color::Cyan),
+            indicator: Style::new().fg(Color::DarkCyan),
+        }
+    }
+
+    /// A theme based on the Solarized Dark palette, using RGB colors.
+    pub fn solarized_dark() -> Self {
+        const BASE01: Color = Color::Rgb { r: 0x58, g: 0x6e, b: 0x75 };
+        const BASE02: Color = Color::Rgb { r: 0x07, g: 0x36, b: 0x42 };
+        const YELLOW: Color = Color::Rgb { r: 0xb5, g: 0x89, b: 0x00 };
+        const ORANGE: Color = Color::Rgb { r: 0xcb, g: 0x4b, b: 0x16 };
+        const RED: Color = Color::Rgb { r: 0xdc, g: 0x32, b: 0x2f };
+        const MAGENTA: Color = Color::Rgb { r: 0xd3, g: 0x36, b: 0x82 };
+        const BLUE: Color = Color::Rgb { r: 0x26, g: 0x8b, b: 0xd2 };
+        const GREEN: Color = Color::Rgb { r: 0x85, g: 0x99, b: 0x00 };
+        Self {
+            prompt_marker: Style::new().fg(YELLOW),
+            prompt: Style::new().fg(GREEN).bold(),
+            continue_prompt: Style::new().fg(BASE01),
+            search_prompt: Style::new().fg(BLUE).italic(),
+            search_match: Style::new().fg(BASE02).bg(YELLOW),
+            hint: Style::new().fg(BASE01),
+            matching_bracket: Style::new().fg(MAGENTA).bold(),
+            error: Style::new().fg(RED),
+            selection: Style::new().bg(BASE02),
+            accent: Style::new().fg(MAGENTA),
+            indicator: Style::new().fg(ORANGE),
+        }
+    }
+
+    /// A theme without colors, as per <https://no-color.org>.
+    /// Only text attributes such as bold and reverse video are used.
+    pub fn no_color() -> Self {
+        Self {
+            prompt_marker: Style::new(),
+            prompt: Style::new().bold(),
+            continue_prompt: Style::new(),
+            search_prompt: Style::new().italic(),
+            search_match: Style::new().underlined(),
+            hint: Style::new().dim(),
+            matching_bracket: Style::new().bold().underlined(),
+            error: Style::new().bold(),
+            selection: Style::new().reverse(),
+            accent: Style::new().bold(),
+            indicator: Style::new().dim(),
+        }
+    }
+
+    /// Return the classic theme, unless the `NO_COLOR` environment variable
+    /// is set to a non-empty value, in which case return `Theme::no_color()`.
+    pub fn from_env() -> Self {
+        let no_color = std::env::var_os("NO_COLOR")
+            .is_some_and(|value| !value.is_empty());
+        if no_color { Self::no_color() } else { Self::classic() }
+    }
+
+    /// Load a theme from a TOML or JSON file.
+    /// The format is determined by the file extension, defaulting to TOML.
+    pub fn read_from_file(filepath: impl AsRef<Utf8Path>) -> ReplBlockResult<Self> {
+        let filepath = filepath.as_ref();
+        let contents = std::fs::read_to_string(filepath)?;
+        match filepath.extension() {
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            _ => toml::from_str(&contents).map_err(ReplBlockError::from),
+        }
+    }
+
+    /// Downgrade the styles of `self` to what a terminal with the
+    /// capabilities `caps` is able to display.
+    pub fn downgrade(&self, caps: &TermCapabilities) -> Self {
+        Self {
+            prompt_marker: self.prompt_marker.downgrade(caps),
+            prompt: self.prompt.downgrade(caps),
+            continue_prompt: self.continue_prompt.downgrade(caps),
+            search_prompt: self.search_prompt.downgrade(caps),
+            search_match: self.search_match.downgrade(caps),
+            hint: self.hint.downgrade(caps),
+            matching_bracket: self.matching_bracket.downgrade(caps),
+            error: self.error.downgrade(caps),
+            selection: self.selection.downgrade(caps),
+            accent: self.accent.downgrade(caps),
+            indicator: self.indicator.downgrade(caps),
+        }
+    }
+
+    /// Return the default prompt, styled according to `self`.
+    pub fn default_prompt_text(&self) -> StyledText {
+        StyledText::new()
+            .with(self.prompt_marker.apply("■"))
+            .with(self.prompt.apply(">"))
+            .with(" ")
+    }
+
+    /// Return the continuation prompt, styled according to `self`.
+    pub fn continue_prompt_text(&self) -> StyledText {
+        StyledText::new()
+            .with(self.continue_prompt.apply(".."))
+            .with(" ")
+    }
+
+    /// Return the reverse search prompt, styled according to `self`.
+    pub fn search_prompt_text(&self) -> StyledText {
+        StyledText::new()
+            .with(self.search_prompt.apply("reverse search:"))
+            .with(" ")
+    }
+}
+
+
+/// A serializable text style.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Style {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fg: Option<Color>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<Color>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dim: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub underlined: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub reverse: bool,
+}
+
+impl Style {
+    pub const fn new() -> Self {
+        Self {
+            fg: None,
+            bg: None,
+            bold: false,
+            dim: false,
+            italic: false,
+            underlined: false,
+            reverse: false,
+        }
+    }
+
+    pub const fn fg(mut self, color: Color) -> Self {
+        self.fg = Some(color);
+        self
+    }
+
+    pub const fn bg(mut self, color: Color) -> Self {
+        self.bg = Some(color);
+        self
+    }
+
+    pub const fn bold(mut self) -> Self {
+        self.bold = true;
+        self
+    }
+
+    pub const fn dim(mut self) -> Self {
+        self.dim = true;
+        self
+    }
+
+    pub const fn italic(mut self) -> Self {
+        self.italic = true;
+        self
+    }
+
+    pub const fn underlined(mut self) -> Self {
+        self.underlined = true;
+        self
+    }
+
+    pub const fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    /// Downgrade `self` to what a terminal with the capabilities `caps`
+    /// is able to display.  On dumb terminals, all styling is removed.
+    pub fn downgrade(&self, caps: &TermCapabilities) -> Self {
+        if caps.dumb {
+            return Self::new();
+        }
+        Self {
+            fg: self.fg.and_then(|color| caps.downgrade_color(color)),
+            bg: self.bg.and_then(|color| caps.downgrade_color(color)),
+            ..*self
+        }
+    }
+
+    /// Apply `self` to some `content`.
+    pub fn apply<D: std::fmt::Display>(&self, content: D) -> StyledContent<D> {
+        StyledContent::new(self.to_content_style(), content)
+    }
+
+    pub fn to_content_style(&self) -> ContentStyle {
+        let mut attributes = Attributes::default();
+        for (enabled, attribute) in [
+            (self.bold, Attribute::Bold),
+            (self.dim, Attribute::Dim),
+            (self.italic, Attribute::Italic),
+            (self.underlined, Attribute::Underlined),
+            (self.reverse, Attribute::Reverse),
+        ] {
+            if enabled {
+                attributes.set(attribute);
+            }
+        }
+        ContentStyle {
+            foreground_color: self.fg,
+            background_color: self.bg,
+            underline_color: None,
+            attributes,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deserialize_themes() -> ReplBlockResult<()> {
+        let toml = r##"
+            prompt = { fg = "green", bold = true }
+            search_match = { fg = "#002b36", bg = "ansi_(220)" }
+        "##;
+        let theme: Theme = toml::from_str(toml)?;
+        assert_eq!(theme.prompt, Style::new().fg(Color::Green).bold());
+        assert_eq!(
+            theme.search_match,
+            Style::new()
+                .fg(Color::Rgb { r: 0x00, g: 0x2b, b: 0x36 })
+                .bg(Color::AnsiValue(220))
+        );
+        assert_eq!(theme.hint, Theme::classic().hint);
+
+        let classic = Theme::classic();
+        let json = serde_json::to_string(&classic)?;
+        assert_eq!(serde_json::from_str::<Theme>(&json)?, classic);
+        Ok(())
+    }
+}
@@ -0,0 +1,264 @@
+//! Recording a session as a `Transcript`, which can later be re-run against
+//! an evaluator to check its output, or played back.
+
+use camino::Utf8Path;
+use crate::capture::StdoutCapture;
+use crate::error::ReplBlockResult;
+use crate::prompt::EvalAction;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The cmds that were evaluated during a session, along with their output
+/// and timing.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+pub struct Transcript {
+    /// When the session started
+    started_at: SystemTime,
+    entries: Vec<TranscriptEntry>,
+}
+
+/// A single cmd in a `Transcript`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize)]
+pub struct TranscriptEntry {
+    /// The source code of the cmd, after history expansion
+    pub input: String,
+    /// What was written to stdout (and to stderr, if that was the same file)
+    /// while the cmd was evaluated, or `None` if it couldn't be captured on
+    /// the platform the session ran on
+    pub output: Option<String>,
+    /// Whether the cmd was a meta-command rather than evaluated by the
+    /// evaluator
+    pub meta: bool,
+    /// When evaluation started, in milliseconds since the session started
+    pub offset_ms: u64,
+    /// How long evaluation took, in milliseconds
+    pub duration_ms: u64,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self { started_at: SystemTime::now(), entries: vec![] }
+    }
+}
+
+impl Transcript {
+    /// Start recording a new session.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_from_file(filepath: impl AsRef<Utf8Path>) -> ReplBlockResult<Self> {
+        let mut contents = String::new();
+        File::open(filepath.as_ref())?.read_to_string(&mut contents)?;
+        Ok(serde_json::from_str::<Self>(&contents)?)
+    }
+
+    pub fn write_to_file(&self, path: impl AsRef<Utf8Path>) -> ReplBlockResult<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(path.as_ref())?;
+        let json: String = serde_json::to_string_pretty(self)?;
+        file.write_all(json.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.entries
+    }
+
+    /// Record that `input` was evaluated from `start` onwards, and that it
+    /// wrote `output`.
+    pub(crate) fn record(
+        &mut self,
+        input: String,
+        output: Option<String>,
+        meta: bool,
+        start: SystemTime,
+    ) {
+        let millis = |duration: Duration| duration.as_millis() as u64;
+        let offset = start.duration_since(self.started_at).unwrap_or_default();
+        let duration = start.elapsed().unwrap_or_default();
+        self.entries.push(TranscriptEntry {
+            input,
+            output,
+            meta,
+            offset_ms: millis(offset),
+            duration_ms: millis(duration),
+        });
+    }
+
+    /// Feed the input of each entry to `evaluator`, and compare what it
+    /// writes to stdout to the recorded output.  The output is captured the
+    /// same way as by the `Repl`, so `evaluator` can be the same one that's
+    /// passed to `ReplBuilder::evaluator()`.  Like in the `Repl`, its errors
+    /// aren't part of the output.
+    /// Meta-commands and entries without recorded output are skipped.
+    /// Trailing newlines are ignored when comparing.
+    /// Return the entries for which the output differs.
+    /// This fails on platforms where output can't be captured.
+    pub fn rerun<E, A>(&self, mut evaluator: E) -> ReplBlockResult<Vec<Mismatch>>
+    where
+        E: for<'src> FnMut(&'src str) -> ReplBlockResult<A>,
+        A: Into<EvalAction>,
+    {
+        self.compare(|input| {
+            let capture = StdoutCapture::start(None)?;
+            if let Err(err) = evaluator(input) {
+                log::debug!("rerun: evaluator error: {err}");
+            }
+            capture.finish()?.ok_or_else(|| {
+                let msg = "capturing output isn't supported on this platform";
+                std::io::Error::new(std::io::ErrorKind::Unsupported, msg).into()
+            })
+        })
+    }
+
+    /// Compare the output that `run` returns for the input of each entry to
+    /// the recorded output, see `Transcript::rerun()`.
+    fn compare<R>(&self, mut run: R) -> ReplBlockResult<Vec<Mismatch>>
+    where
+        R: FnMut(&str) -> ReplBlockResult<String>,
+    {
+        let mut mismatches = vec![];
+        for (idx, entry) in self.entries.iter().enumerate() {
+            let Some(expected) = entry.output.as_deref().filter(|_| !entry.meta) else {
+                continue;
+            };
+            let actual = run(&entry.input)?;
+            if expected.trim_end_matches('\n') != actual.trim_end_matches('\n') {
+                mismatches.push(Mismatch {
+                    idx,
+                    input: entry.input.clone(),
+                    expected: expected.to_string(),
+                    actual,
+                });
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Play the session back on `sink`: each cmd is typed out after
+    /// `prompt`, followed by its recorded output.  The recorded timing is
+    /// sped up by a factor `speed`, and idle time between cmds is capped.
+    pub fn play(&self, sink: &mut impl Write, prompt: &str, speed: f64) -> ReplBlockResult<()> {
+        const KEYSTROKE: Duration = Duration::from_millis(40);
+        const MAX_IDLE: Duration = Duration::from_secs(2);
+        let speed = if speed > 0.0 { speed } else { 1.0 };
+        let pause = |duration: Duration| std::thread::sleep(duration.div_f64(speed));
+        let mut prev_end_ms = 0;
+        for entry in &self.entries {
+            write!(sink, "{prompt}")?;
+            sink.flush()?;
+            let idle = Duration::from_millis(entry.offset_ms.saturating_sub(prev_end_ms));
+            pause(std::cmp::min(idle, MAX_IDLE));
+            for (idx, line) in entry.input.split('\n').enumerate() {
+                if idx > 0 {
+                    writeln!(sink)?;
+                }
+                for grapheme in line.graphemes(true) {
+                    write!(sink, "{grapheme}")?;
+                    sink.flush()?;
+                    pause(KEYSTROKE);
+                }
+            }
+            writeln!(sink)?;
+            pause(Duration::from_millis(entry.duration_ms));
+            if let Some(output) = &entry.output {
+                write!(sink, "{output}")?;
+            }
+            sink.flush()?;
+            prev_end_ms = entry.offset_ms + entry.duration_ms;
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a `Transcript` whose output differs from the output of a
+/// re-run.  It's displayed as a line-based diff.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Mismatch {
+    /// The index of the entry in the `Transcript`
+    pub idx: usize,
+    pub input: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "entry {}: {}", self.idx, self.input)?;
+        let expected: Vec<&str> = self.expected.lines().collect();
+        let actual: Vec<&str> = self.actual.lines().collect();
+        for (tag, line) in diff_lines(&expected, &actual) {
+            writeln!(f, "{tag}{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Diff two sequences of lines using their longest common subsequence.
+/// Each line is tagged with `' '` if it's in both, `'-'` if it's only in
+/// `old`, and `'+'` if it's only in `new`.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+    // lcs[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut diff = vec![];
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            diff.push((' ', old[i]));
+            (i, j) = (i + 1, j + 1);
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(('-', old[i]));
+            i += 1;
+        } else {
+            diff.push(('+', new[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old[i..].iter().map(|line| ('-', *line)));
+    diff.extend(new[j..].iter().map(|line| ('+', *line)));
+    diff
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rerun_reports_diffs() -> ReplBlockResult<()> {
+        let mut transcript = Transcript::new();
+        let start = SystemTime::now();
+        transcript.record("double 2".into(), Some("4\n".into()), false, start);
+        transcript.record("lines 3".into(), Some("1\n2\n3\n".into()), false, start);
+        transcript.record(":help".into(), Some("Meta-commands:\n".into()), true, start);
+        let json = serde_json::to_string(&transcript)?;
+        assert_eq!(serde_json::from_str::<Transcript>(&json)?, transcript);
+
+        let mismatches = transcript.compare(|input: &str| {
+            Ok(match input {
+                "double 2" => "4".to_string(),
+                "lines 3" => "1\nthree\n3".to_string(),
+                _ => unreachable!("meta-commands aren't re-run"),
+            })
+        })?;
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].idx, 1);
+        assert_eq!(mismatches[0].to_string(), "entry 1: lines 3\n 1\n-2\n+three\n 3\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! The terminal mode that's used while a cmd is being evaluated.
+//! While editing, the terminal is in raw mode for the whole session.
+
+use crossterm::terminal;
+
+/// While an `EvalMode` is alive, the terminal is set up for evaluating a cmd:
+/// raw mode is off so that output is processed as usual e.g. `\n` starts a
+/// new line.  In quiet mode, on Unix input is neither echoed nor line
+/// buffered either, so that keys typed during evaluation don't clobber the
+/// output.  Either way, they can be replayed into the next prompt.
+pub(crate) struct EvalMode {
+    /// The terminal settings from before echoing was turned off
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl EvalMode {
+    pub(crate) fn enter(quiet: bool) -> std::io::Result<Self> {
+        terminal::disable_raw_mode()?;
+        #[cfg(unix)]
+        {
+            if !quiet {
+                return Ok(Self { saved: None });
+            }
+            // SAFETY: `termios` is a plain C struct, for which all zeroes is
+            //         a valid value, and the fd is checked to be a terminal.
+            unsafe {
+                if libc::isatty(libc::STDIN_FILENO) != 1 {
+                    return Ok(Self { saved: None });
+                }
+                let mut saved: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                let mut quiet = saved;
+                quiet.c_lflag &= !(libc::ECHO | libc::ICANON);
+                quiet.c_cc[libc::VMIN] = 1;
+                quiet.c_cc[libc::VTIME] = 0;
+                if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &quiet) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(Self { saved: Some(saved) })
+            }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = quiet;
+            Ok(Self {})
+        }
+    }
+
+    /// Leave eval mode, and enable raw mode again.
+    pub(crate) fn leave(self) -> std::io::Result<()> {
+        drop(self);
+        terminal::enable_raw_mode()
+    }
+}
+
+impl Drop for EvalMode {
+    /// Restore echoing and line buffering, even when unwinding from a panic
+    /// in the evaluator.
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = self.saved.take() {
+            // SAFETY: `saved` was obtained from `tcgetattr()` on the same fd.
+            //         `TCSANOW` keeps the typeahead in the input queue.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved) };
+        }
+    }
+}